@@ -0,0 +1,808 @@
+use crate::{OPT, PAGE};
+use crate::report::{self, LoadError};
+use crate::utils::{self, Initiator};
+
+use anyhow::Result;
+use kuchiki::{ElementData, NodeDataRef, NodeRef, Selectors};
+use kuchiki::iter::NodeIterator;
+use once_cell::sync::OnceCell;
+use regex::Regex;
+use url::Url;
+
+use std::collections::{HashMap, HashSet};
+
+pub static LISTS: OnceCell<FilterList> = OnceCell::new();
+
+// `example.com##.ad`, `~example.com,example.org#@#.ad`, `#?#` and `#$#` are extended syntax
+regex!(static RE_COSMETIC, r#"^(?P<domains>[^/*|@"!]*?)#(?P<exception>@)?(?P<extended>[?$%])?#(?P<selector>.+)$"#);
+
+// what comes after `$` in `||example.com^$script,third-party`
+regex!(static RE_OPTIONS, r#"^~?[\w-]+(=[^,]*)?(,~?[\w-]+(=[^,]*)?)*$"#);
+
+// `||example.com^` alone can be looked up by host instead of matched
+regex!(static RE_HOST_RULE, r#"^\|\|(?P<host>[a-z0-9.-]+)\^?$"#);
+
+// `##.ad` and `###banner` are looked up instead of matched too
+regex!(static RE_SIMPLE_SELECTOR, r#"^(?P<kind>[#.])(?P<name>[\w-]+)$"#);
+
+/// Request types a network rule can be limited to; anything else skips the rule
+const TYPES: &[(&str, &str)] = &[
+    ("script", "script"),
+    ("image", "image"),
+    ("stylesheet", "stylesheet"),
+    ("css", "stylesheet"),
+    ("font", "font"),
+    ("media", "media"),
+    ("object", "object"),
+    ("subdocument", "subdocument"),
+    ("frame", "subdocument"),
+    ("xmlhttprequest", "xmlhttprequest"),
+    ("xhr", "xmlhttprequest"),
+    ("other", "other"),
+];
+
+/// Network and element hiding rules of Adblock Plus/EasyList filter lists
+#[derive(Debug, Default)]
+pub struct FilterList {
+    blocks: Vec<NetworkRule>,
+    exceptions: Vec<NetworkRule>,
+    // `||host^` rules by host, indexes into `blocks`
+    hosts: HashMap<String, Vec<usize>>,
+    hides: Vec<CosmeticRule>,
+    unhides: Vec<CosmeticRule>,
+}
+
+#[derive(Debug)]
+struct NetworkRule {
+    text: String,
+    pattern: Pattern,
+    types: Vec<&'static str>,
+    not_types: Vec<&'static str>,
+    third_party: Option<bool>,
+    domains: Domains,
+    // `$important` blocks even if an exception allows
+    important: bool,
+}
+
+#[derive(Debug)]
+enum Pattern {
+    // `||host^`, matched by `hosts` already
+    Host(String),
+    Regex {
+        source: String,
+        // longest literal part, no need to run the regex without it
+        literal: Option<String>,
+        case: bool,
+        re: OnceCell<Option<Regex>>,
+    },
+}
+
+#[derive(Debug)]
+struct CosmeticRule {
+    text: String,
+    selector: String,
+    domains: Domains,
+}
+
+/// What an element needs to have to match a selector, from its rightmost compound
+#[derive(Debug, PartialEq)]
+enum Key {
+    Id(String),
+    Class(String),
+    Attr(String),
+    Tag(String),
+}
+
+/// `domain=a.com|~b.a.com` or `a.com,~b.a.com##`
+#[derive(Debug, Default)]
+struct Domains {
+    include: Vec<String>,
+    exclude: Vec<String>,
+}
+
+impl Domains {
+    fn parse<'a>(list: impl Iterator<Item = &'a str>) -> Domains {
+        let mut domains = Domains::default();
+
+        for d in list.map(str::trim).filter(|d| !d.is_empty()) {
+            match d.strip_prefix('~') {
+                Some(d) => domains.exclude.push(d.to_lowercase()),
+                None => domains.include.push(d.to_lowercase()),
+            }
+        }
+
+        domains
+    }
+
+    fn is_generic(&self) -> bool {
+        self.include.is_empty()
+    }
+
+    fn applies(&self, page: Option<&str>) -> bool {
+        let on = |d: &String| page.is_some_and(|host| host == d || host.ends_with(&format!(".{}", d)));
+
+        (self.include.is_empty() || self.include.iter().any(on)) && !self.exclude.iter().any(on)
+    }
+}
+
+impl FilterList {
+    /// Adds the rules of one list; comments and rules this can't apply are skipped
+    pub fn add(&mut self, text: &str) {
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('!') || line.starts_with('[') {
+                continue;
+            }
+
+            if let Some(cap) = RE_COSMETIC.captures(line) {
+                // extended CSS, snippets and scriptlets need a browser
+                if cap.name("extended").is_some() || cap["selector"].starts_with("+js(") || cap["selector"].starts_with('^') {
+                    continue;
+                }
+
+                let rule = CosmeticRule {
+                    text: line.to_owned(),
+                    selector: cap["selector"].to_owned(),
+                    domains: Domains::parse(cap["domains"].split(',')),
+                };
+
+                match cap.name("exception") {
+                    Some(_) => self.unhides.push(rule),
+                    None => self.hides.push(rule),
+                }
+
+                continue;
+            }
+
+            let (exception, rule) = match line.strip_prefix("@@") {
+                Some(rule) => (true, rule),
+                None => (false, line),
+            };
+
+            let rule = match NetworkRule::parse(line, rule, exception) {
+                Some(rule) => rule,
+                None => {
+                    log!(debug, "skipping filter `{}`", line);
+                    continue;
+                }
+            };
+
+            if exception {
+                self.exceptions.push(rule);
+                continue;
+            }
+
+            if let Pattern::Host(ref host) = rule.pattern {
+                self.hosts.entry(host.to_owned()).or_default().push(self.blocks.len());
+            }
+
+            self.blocks.push(rule);
+        }
+    }
+
+    /// The rule that blocks `url` requested as `kind` by `page`, unless an exception allows it
+    pub fn blocking(&self, url: &Url, kind: &str, page: Option<&Url>) -> Option<&str> {
+        let host = url.host_str().map(str::to_lowercase);
+        let page_host = page.and_then(Url::host_str).map(str::to_lowercase);
+
+        let third_party = match (&host, &page_host) {
+            (Some(host), Some(page)) => site(host) != site(page),
+            (host, _) => host.is_some(),
+        };
+
+        let applies = |r: &&NetworkRule| r.options_match(kind, third_party, page_host.as_deref());
+
+        // every parent domain of the host may have a `||host^` rule
+        let by_host = host.iter()
+                          .flat_map(|h| h.match_indices('.').map(move |(i, _)| &h[i + 1..]).chain(Some(h.as_str())))
+                          .filter_map(|h| self.hosts.get(h))
+                          .flatten()
+                          .map(|&i| &self.blocks[i]);
+
+        let mut matching = by_host.chain(self.blocks.iter().filter(|r| !matches!(r.pattern, Pattern::Host(_))))
+                                  .filter(applies)
+                                  .filter(|r| r.pattern.matches(url));
+
+        let rule = matching.next()?;
+
+        // exceptions don't count if any of the rules is important
+        if let Some(important) = std::iter::once(rule).chain(matching).find(|r| r.important) {
+            return Some(&important.text);
+        }
+
+        match self.exceptions.iter().filter(applies).find(|r| r.pattern.matches(url)) {
+            Some(exception) => {
+                log!(debug, "{} is allowed by `{}`", url, exception.text);
+                None
+            }
+            None => Some(&rule.text),
+        }
+    }
+
+    /// Elements matched by element hiding rules for `page`, with the rule hiding them
+    pub fn hidden(&self, html: &NodeRef, page: Option<&Url>) -> Vec<(NodeDataRef<ElementData>, &str)> {
+        let page_host = page.and_then(Url::host_str).map(str::to_lowercase);
+
+        let unhidden = self.unhides
+                           .iter()
+                           .filter(|r| r.domains.applies(page_host.as_deref()))
+                           .map(|r| r.selector.as_str())
+                           .collect::<HashSet<_>>();
+
+        let mut ids = HashMap::new();
+        let mut classes = HashMap::new();
+        let mut complex = vec![];
+
+        // complex selectors by what their rightmost compound needs, indexes into `complex`
+        let mut complex_ids: HashMap<String, Vec<usize>> = HashMap::new();
+        let mut complex_classes: HashMap<String, Vec<usize>> = HashMap::new();
+        let mut complex_attrs: HashMap<String, Vec<usize>> = HashMap::new();
+        let mut complex_tags: HashMap<String, Vec<usize>> = HashMap::new();
+        let mut unkeyed = vec![];
+
+        for rule in &self.hides {
+            if unhidden.contains(rule.selector.as_str()) || !rule.domains.applies(page_host.as_deref()) {
+                continue;
+            }
+
+            match RE_SIMPLE_SELECTOR.captures(&rule.selector) {
+                Some(cap) if &cap["kind"] == "#" => { ids.entry(cap["name"].to_owned()).or_insert(rule); }
+                Some(cap) => { classes.entry(cap["name"].to_owned()).or_insert(rule); }
+                // kuchiki doesn't know every selector browsers do
+                None => if let Ok(selectors) = Selectors::compile(&rule.selector) {
+                    let i = complex.len();
+                    complex.push((selectors, rule));
+
+                    // `a, b` can match whatever either of them can
+                    let keys = split_top_level(&rule.selector, ',')
+                        .into_iter()
+                        .map(key)
+                        .collect::<Option<Vec<_>>>();
+
+                    match keys {
+                        Some(keys) => for key in keys {
+                            match key {
+                                Key::Id(id) => complex_ids.entry(id).or_default().push(i),
+                                Key::Class(class) => complex_classes.entry(class).or_default().push(i),
+                                Key::Attr(attr) => complex_attrs.entry(attr).or_default().push(i),
+                                Key::Tag(tag) => complex_tags.entry(tag).or_default().push(i),
+                            }
+                        },
+                        None => unkeyed.push(i),
+                    }
+                } else if !rule.domains.is_generic() {
+                    log!(debug, "skipping filter `{}`", rule.text);
+                },
+            }
+        }
+
+        html.descendants()
+            .elements()
+            .filter_map(|elm| {
+                let (rule, mut candidates) = {
+                    let attrs = elm.attributes.borrow();
+                    let class = attrs.get("class").unwrap_or_default();
+                    let tag = elm.name.local.to_lowercase();
+
+                    let rule = attrs.get("id")
+                                    .and_then(|id| ids.get(id))
+                                    .or_else(|| class.split_ascii_whitespace().find_map(|c| classes.get(c)))
+                                    .copied();
+
+                    // only the complex selectors this element may match are tried
+                    let candidates = attrs.get("id")
+                                          .and_then(|id| complex_ids.get(id))
+                                          .into_iter()
+                                          .chain(class.split_ascii_whitespace().filter_map(|c| complex_classes.get(c)))
+                                          .chain(attrs.map.keys().filter_map(|a| complex_attrs.get(a.local.as_ref())))
+                                          .chain(complex_tags.get(&tag))
+                                          .flatten()
+                                          .chain(&unkeyed)
+                                          .copied()
+                                          .collect::<Vec<_>>();
+
+                    (rule, candidates)
+                };
+
+                // the first rule of the lists wins, as if all were tried in order
+                candidates.sort_unstable();
+                candidates.dedup();
+
+                rule.or_else(|| candidates.into_iter().map(|i| &complex[i]).find(|(s, _)| s.matches(&elm)).map(|(_, r)| *r))
+                    .map(|r| (elm, r.text.as_str()))
+            })
+            .collect()
+    }
+}
+
+impl NetworkRule {
+    fn parse(line: &str, rule: &str, exception: bool) -> Option<NetworkRule> {
+        let (pattern, options) = match rule.rsplit_once('$') {
+            Some((pattern, options)) if RE_OPTIONS.is_match(options) => (pattern, options),
+            _ => (rule, ""),
+        };
+
+        let mut parsed = NetworkRule {
+            text: line.to_owned(),
+            pattern: Pattern::Host(String::new()),
+            types: vec![],
+            not_types: vec![],
+            third_party: None,
+            domains: Domains::default(),
+            important: false,
+        };
+
+        let mut case = false;
+
+        for option in options.split(',').filter(|o| !o.is_empty()) {
+            let (negated, name) = match option.strip_prefix('~') {
+                Some(name) => (true, name),
+                None => (false, option),
+            };
+
+            match name.split_once('=') {
+                Some(("domain", domains)) => parsed.domains = Domains::parse(domains.split('|')),
+                Some(_) => return None,
+                None => match name {
+                    "third-party" | "3p" => parsed.third_party = Some(!negated),
+                    "first-party" | "1p" => parsed.third_party = Some(negated),
+                    "match-case" => case = true,
+                    "important" => parsed.important = true,
+                    "all" => {}
+                    _ => {
+                        let kind = TYPES.iter().find(|(n, _)| *n == name)?.1;
+
+                        match negated {
+                            true => parsed.not_types.push(kind),
+                            false => parsed.types.push(kind),
+                        }
+                    }
+                },
+            }
+        }
+
+        // too broad to be meant for every request
+        if pattern.is_empty() || pattern == "*" {
+            return None;
+        }
+
+        // exceptions are few, they are all matched
+        parsed.pattern = match RE_HOST_RULE.captures(pattern) {
+            Some(cap) if !exception => Pattern::Host(cap["host"].to_owned()),
+            _ => {
+                let (source, literal) = pattern_to_regex(pattern)?;
+
+                Pattern::Regex {
+                    literal: literal.map(|l| if case { l } else { l.to_lowercase() }),
+                    source,
+                    case,
+                    re: OnceCell::new(),
+                }
+            }
+        };
+
+        Some(parsed)
+    }
+
+    fn options_match(&self, kind: &str, third_party: bool, page: Option<&str>) -> bool {
+        (self.types.is_empty() || self.types.contains(&kind))
+            && !self.not_types.contains(&kind)
+            && self.third_party.is_none_or(|t| t == third_party)
+            && self.domains.applies(page)
+    }
+}
+
+impl Pattern {
+    fn matches(&self, url: &Url) -> bool {
+        let (source, literal, case, re) = match self {
+            Pattern::Host(_) => return true,
+            Pattern::Regex { source, literal, case, re } => (source, literal, case, re),
+        };
+
+        if let Some(literal) = literal {
+            let found = match case {
+                true => url.as_str().contains(literal.as_str()),
+                false => url.as_str().to_lowercase().contains(literal.as_str()),
+            };
+
+            if !found {
+                return false;
+            }
+        }
+
+        re.get_or_init(|| {
+              let flags = if *case { "" } else { "(?i)" };
+              Regex::new(&format!("{}{}", flags, source)).ok()
+          })
+          .as_ref()
+          .is_some_and(|re| re.is_match(url.as_str()))
+    }
+}
+
+// `||` is any scheme and subdomain, `|` an anchor, `^` a separator, `*` anything;
+// `/.../` is a regex already
+fn pattern_to_regex(pattern: &str) -> Option<(String, Option<String>)> {
+    if pattern.len() > 2 && pattern.starts_with('/') && pattern.ends_with('/') {
+        // lists escape `/` as in JavaScript, the regex crate doesn't want that
+        let mut source = String::new();
+        let mut chars = pattern[1..pattern.len() - 1].chars();
+
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                source.push(c);
+                continue;
+            }
+
+            match chars.next() {
+                Some('/') => source.push('/'),
+                Some(c) => source.extend(['\\', c]),
+                None => source.push(c),
+            }
+        }
+
+        return Regex::new(&source).is_ok().then_some((source, None));
+    }
+
+    let mut re = String::new();
+    let mut rest = pattern;
+
+    if let Some(r) = rest.strip_prefix("||") {
+        re.push_str(r"^[a-z][a-z0-9+.-]*://(?:[^/?#]*\.)?");
+        rest = r;
+    } else if let Some(r) = rest.strip_prefix('|') {
+        re.push('^');
+        rest = r;
+    }
+
+    let anchored = rest.strip_suffix('|');
+    let body = anchored.unwrap_or(rest);
+
+    for c in body.chars() {
+        match c {
+            '*' => re.push_str(".*"),
+            '^' => re.push_str(r"(?:[^\w.%-]|$)"),
+            c => re.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+
+    if anchored.is_some() {
+        re.push('$');
+    }
+
+    let literal = body.split(['*', '^', '|'])
+                      .max_by_key(|l| l.len())
+                      .filter(|l| !l.is_empty())
+                      .map(str::to_owned);
+
+    Some((re, literal))
+}
+
+// `sep`s outside of `[...]`, `(...)` and quotes
+fn split_top_level(selector: &str, sep: char) -> Vec<&str> {
+    let mut parts = vec![];
+    let mut depth = 0usize;
+    let mut quote = None;
+    let mut start = 0;
+
+    for (i, c) in selector.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (None, '[') | (None, '(') => depth += 1,
+            (None, ']') | (None, ')') => depth = depth.saturating_sub(1),
+            (None, c) if c == sep && depth == 0 => {
+                parts.push(&selector[start..i]);
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+
+    parts.push(&selector[start..]);
+    parts
+}
+
+// `#id`, `.class`, `[attr]` or tag of the compound after the last combinator,
+// in that order; `None` if it can be any element
+fn key(selector: &str) -> Option<Key> {
+    let selector = selector.trim();
+
+    let compound = [' ', '>', '+', '~']
+        .iter()
+        .filter_map(|&c| split_top_level(selector, c).pop())
+        .min_by_key(|s| s.len())?;
+
+    // escapes and namespaces are too rare to bother
+    if compound.contains(['\\', '|']) {
+        return None;
+    }
+
+    let ident = |s: &str| {
+        let end = s.find(|c: char| !(c.is_alphanumeric() || c == '-' || c == '_')).unwrap_or(s.len());
+        Some(s[..end].to_owned()).filter(|s| !s.is_empty())
+    };
+
+    // nothing in `[...]` or `:not(...)` counts but attribute names
+    let mut top = String::new();
+    let mut attrs = vec![];
+    let mut depth = 0usize;
+    let mut quote = None;
+
+    for (i, c) in compound.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (None, '[') => {
+                if depth == 0 {
+                    attrs.extend(ident(compound[i + 1..].trim_start()));
+                }
+                depth += 1;
+            }
+            (None, '(') => depth += 1,
+            (None, ']') | (None, ')') => depth = depth.saturating_sub(1),
+            (None, c) if depth == 0 => top.push(c),
+            _ => {}
+        }
+    }
+
+    let after = |marker: char| top.match_indices(marker).find_map(|(i, _)| ident(&top[i + 1..]));
+
+    after('#').map(Key::Id)
+              .or_else(|| after('.').map(Key::Class))
+              .or_else(|| attrs.first().map(|a| Key::Attr(a.to_lowercase())))
+              .or_else(|| ident(&top).map(|t| Key::Tag(t.to_lowercase())))
+}
+
+// last two labels, close enough to a registrable domain without the public suffix list
+fn site(host: &str) -> &str {
+    host.rmatch_indices('.')
+        .nth(1)
+        .map_or(host, |(i, _)| &host[i + 1..])
+}
+
+/// Filter list sources are files or URLs
+pub fn load(sources: &[String]) -> Result<FilterList> {
+    let mut list = FilterList::default();
+
+    for source in sources {
+        let text = match Url::parse(source) {
+            Ok(url) if url.scheme() == "http" || url.scheme() == "https" => {
+                String::from_utf8_lossy(&utils::load_url(&url)?.data).into_owned()
+            }
+            _ => std::fs::read_to_string(source)?,
+        };
+
+        list.add(&text);
+    }
+
+    log!(info, "{} network rule(s), {} exception(s), {} element hiding rule(s)",
+        list.blocks.len(), list.exceptions.len(), list.hides.len());
+
+    Ok(list)
+}
+
+/// Request type of `url` for `$script`, `$image` and the like
+fn request_type(url: &Url, handler: Option<&str>) -> &'static str {
+    let by_mime = mime_guess::from_path(url.path())
+        .first()
+        .and_then(|mime| match (mime.type_().as_str(), mime.subtype().as_str()) {
+            ("text", "css") => Some("stylesheet"),
+            (_, "javascript") => Some("script"),
+            ("image", _) => Some("image"),
+            ("font", _) | (_, "font-woff") | (_, "vnd.ms-fontobject") => Some("font"),
+            ("audio", _) | ("video", _) => Some("media"),
+            _ => None,
+        });
+
+    // `image::srcset` is an image, so is what a stylesheet's `url()` loads
+    by_mime.unwrap_or(match handler {
+        Some(h) if h.starts_with("script") => "script",
+        Some(h) if h.starts_with("image") || h.ends_with("::url") || h == "favicon" => "image",
+        Some(h) if h.starts_with("css") => "stylesheet",
+        _ => "other",
+    })
+}
+
+/// Whether a network rule blocks `url`, asked for by the current handler
+pub fn check(url: &Url) -> Result<(), LoadError> {
+    let list = match LISTS.get() {
+        Some(list) if url.scheme() != "data" => list,
+        _ => return Ok(()),
+    };
+
+    let kind = request_type(url, utils::initiator().as_ref().map(|i| i.handler.as_str()));
+
+    match list.blocking(url, kind, Some(&OPT.get_base())) {
+        Some(rule) => Err(LoadError::Blocked(rule.to_owned())),
+        None => Ok(()),
+    }
+}
+
+/// Drops hidden elements and elements pulling in blocked resources, before handlers see them
+pub fn apply(html: &NodeRef) {
+    let list = match LISTS.get() {
+        Some(list) => list,
+        None => return,
+    };
+
+    let base = OPT.get_base();
+    let page = Some(&base);
+
+    let blocked = html.select("script[src], img[src], iframe[src], link[rel=stylesheet][href]")
+                      .map_or(vec![], |v| v.collect())
+                      .into_iter()
+                      .filter_map(|elm| {
+                          let (url, kind) = {
+                              let attrs = elm.attributes.borrow();
+                              let (attr, kind) = match elm.name.local.as_ref() {
+                                  "script" => ("src", "script"),
+                                  "img" => ("src", "image"),
+                                  "iframe" => ("src", "subdocument"),
+                                  _ => ("href", "stylesheet"),
+                              };
+                              (base.join(attrs.get(attr)?).ok()?, kind)
+                          };
+
+                          let rule = list.blocking(&url, kind, page)?;
+                          Some((elm, url.to_string(), rule))
+                      })
+                      .collect::<Vec<_>>();
+
+    let hidden = list.hidden(html, page);
+    let page_url = PAGE.get().cloned().or_else(|| OPT.get_page()).unwrap_or_else(|| base.clone()).to_string();
+
+    let removed = blocked.into_iter()
+                         .chain(hidden.into_iter().map(|(elm, rule)| (elm, page_url.clone(), rule)));
+
+    for (elm, url, rule) in removed {
+        // already gone with an ancestor
+        if elm.as_node().ancestors().last().is_none_or(|root| root.as_document().is_none()) {
+            continue;
+        }
+
+        utils::with_initiator(Some(Initiator::new("adblock", &elm)), || {
+            report::failed(&url, &LoadError::Blocked(rule.to_owned()).into());
+        });
+
+        elm.as_node().detach();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIST: &str = r#"[Adblock Plus 2.0]
+! Title: test list
+||ads.example.com^
+||tracker.net^$third-party
+/banner/*/ad_$image
+|https://cdn.example.org/pixel.gif|
+@@||ads.example.com/allowed/
+||ads.example.com/allowed/beacon.js$important
+||metrics.example^$important
+@@||metrics.example^$image
+||widgets.example^$script,domain=news.example|~sports.news.example
+/\/track\.(js|php)\?id=\d+/
+||example.com^$csp=script-src 'none'
+##.ad-banner
+###sponsored
+news.example##div[data-ad]
+~quiet.example##.promo
+example.com#@#.ad-banner
+example.com#?#div:-abp-has(> .ad)
+##.sidebar > div.sponsored-slot
+##aside[id^="ad-"], *[data-sponsored]
+"#;
+
+    fn list() -> FilterList {
+        let mut list = FilterList::default();
+        list.add(LIST);
+        list
+    }
+
+    #[test]
+    fn network_rules() {
+        let list = list();
+        let url = |s: &str| Url::parse(s).unwrap();
+        let news = url("https://www.news.example/");
+        let page = Some(&news);
+
+        let blocked = |u: &str, kind: &str, page: Option<&Url>| list.blocking(&url(u), kind, page).map(str::to_owned);
+
+        assert_eq!(blocked("https://ads.example.com/a.js", "script", page).as_deref(), Some("||ads.example.com^"));
+        assert_eq!(blocked("http://img.ads.example.com:8080/a.png", "image", page).as_deref(), Some("||ads.example.com^"));
+        assert_eq!(blocked("https://notads.example.com/a.js", "script", page), None);
+        assert_eq!(blocked("https://ads.example.com/allowed/a.js", "script", page), None);
+
+        // exceptions don't undo $important
+        assert_eq!(blocked("https://ads.example.com/allowed/beacon.js", "script", page).as_deref(),
+                   Some("||ads.example.com/allowed/beacon.js$important"));
+        assert_eq!(blocked("https://metrics.example/pixel.gif", "image", page).as_deref(), Some("||metrics.example^$important"));
+
+        assert!(blocked("https://tracker.net/t.js", "script", page).is_some());
+        assert_eq!(blocked("https://tracker.net/t.js", "script", Some(&url("https://www.tracker.net/"))), None);
+
+        assert!(blocked("https://x.example/banner/300/ad_1.png", "image", page).is_some());
+        assert_eq!(blocked("https://x.example/banner/300/ad_1.png", "script", page), None);
+
+        assert!(blocked("https://cdn.example.org/pixel.gif", "image", page).is_some());
+        assert_eq!(blocked("https://cdn.example.org/pixel.gif?x=1", "image", page), None);
+
+        assert!(blocked("https://widgets.example/w.js", "script", page).is_some());
+        assert_eq!(blocked("https://widgets.example/w.js", "script", Some(&url("https://sports.news.example/"))), None);
+        assert_eq!(blocked("https://widgets.example/w.js", "script", Some(&url("https://blog.example/"))), None);
+
+        assert!(blocked("https://stats.example/track.php?id=42", "other", page).is_some());
+
+        // rules with options that can't be honoured are left out
+        assert_eq!(blocked("https://example.com/", "other", page), None);
+    }
+
+    #[test]
+    fn request_types() {
+        let kind = |url: &str, handler: &str| request_type(&Url::parse(url).unwrap(), Some(handler));
+
+        assert_eq!(kind("https://example.com/photo?w=640", "image::srcset"), "image");
+        assert_eq!(kind("https://example.com/bg?id=3", "css::inline::url"), "image");
+        assert_eq!(kind("https://example.com/fonts/a.woff2", "css::external::url"), "font");
+        assert_eq!(kind("https://example.com/theme?v=2", "css::internal"), "stylesheet");
+        assert_eq!(kind("https://example.com/track", "adblock"), "other");
+    }
+
+    #[test]
+    fn element_hiding() {
+        use kuchiki::traits::*;
+
+        let list = list();
+        let html = kuchiki::parse_html().one(r#"
+            <div class="box ad-banner">a</div>
+            <div id="sponsored">b</div>
+            <div data-ad="1">c</div>
+            <div class="content">d</div>
+            <div class="promo">e</div>
+            <div class="sidebar"><div class="sponsored-slot">f</div></div>
+            <div class="sponsored-slot">g</div>
+            <aside id="ad-top">h</aside>
+            <p data-sponsored>i</p>
+        "#);
+
+        let hidden = |page: &str| list.hidden(&html, Some(&Url::parse(page).unwrap()))
+                                      .into_iter()
+                                      .map(|(elm, rule)| (elm.text_contents(), rule.to_owned()))
+                                      .collect::<Vec<_>>();
+
+        let pair = |text: &str, rule: &str| (text.to_owned(), rule.to_owned());
+
+        let complex = [
+            pair("f", "##.sidebar > div.sponsored-slot"),
+            pair("h", r#"##aside[id^="ad-"], *[data-sponsored]"#),
+            pair("i", r#"##aside[id^="ad-"], *[data-sponsored]"#),
+        ];
+
+        assert_eq!(hidden("https://news.example/"),
+                   [&[pair("a", "##.ad-banner"), pair("b", "###sponsored"), pair("c", "news.example##div[data-ad]"),
+                      pair("e", "~quiet.example##.promo")][..], &complex].concat());
+        assert_eq!(hidden("https://www.example.com/"),
+                   [&[pair("b", "###sponsored"), pair("e", "~quiet.example##.promo")][..], &complex].concat());
+        assert_eq!(hidden("https://quiet.example/"),
+                   [&[pair("a", "##.ad-banner"), pair("b", "###sponsored")][..], &complex].concat());
+    }
+
+    #[test]
+    fn selector_keys() {
+        let class = |c: &str| Some(Key::Class(c.to_owned()));
+
+        assert_eq!(key("div.sidebar > .ad-slot"), class("ad-slot"));
+        assert_eq!(key("aside#top .ad:first-child"), class("ad"));
+        assert_eq!(key("DIV.Promo#Box"), Some(Key::Id("Box".to_owned())));
+        assert_eq!(key(r#"a[title='x > y'] + IMG[ SRC*=".ad"]"#), Some(Key::Attr("src".to_owned())));
+        assert_eq!(key(r#"a[title="x]#y"]"#), Some(Key::Attr("title".to_owned())));
+        assert_eq!(key("a:not(.x)"), Some(Key::Tag("a".to_owned())));
+        assert_eq!(key("*:not([data-ad])"), None);
+        assert_eq!(key(":is(div, span).ad"), class("ad"));
+
+        assert_eq!(split_top_level(r#"a[title="1, 2"], b:is(i, u)"#, ','), [r#"a[title="1, 2"]"#, " b:is(i, u)"]);
+    }
+}
@@ -0,0 +1,374 @@
+use crate::compress;
+use crate::report::LoadError;
+
+use anyhow::{Result, anyhow, bail};
+use flate2::read::MultiGzDecoder;
+use once_cell::sync::OnceCell;
+use serde_json::Value;
+use url::Url;
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+
+// loaded once in main() when --from-archive is given
+pub static ARCHIVE: OnceCell<Archive> = OnceCell::new();
+
+/// What the archive has for a URL
+pub struct Archived {
+    /// Where archived redirects ended up
+    pub url: Url,
+    pub mime: Option<String>,
+    pub data: Vec<u8>,
+    /// `(URL, status, Location)` of every archived redirect on the way
+    pub redirects: Vec<(Url, i32, String)>,
+}
+
+/// What to do when a URL is not in the archive
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fallback {
+    Fail,
+    Network,
+}
+
+impl FromStr for Fallback {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "fail" => Ok(Fallback::Fail),
+            "network" => Ok(Fallback::Network),
+            _ => bail!("unknown fallback `{}` (use `fail` or `network`)", s),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Record {
+    status: i32,
+    mime: Option<String>,
+    location: Option<String>,
+    data: Vec<u8>,
+}
+
+/// URL-indexed responses from a WARC (optionally gzipped) or HAR capture
+#[derive(Debug, Default)]
+pub struct Archive {
+    records: HashMap<String, Record>,
+}
+
+impl Archive {
+    pub fn open(path: &Path) -> Result<Archive> {
+        let mut data = fs::read(path)?;
+
+        if data.starts_with(&[0x1f, 0x8b]) {
+            let mut buf = Vec::new();
+            MultiGzDecoder::new(data.as_slice()).read_to_end(&mut buf)?;
+            data = buf;
+        }
+
+        let archive = if data.starts_with(b"WARC/") {
+            Archive::from_warc(&data)?
+        } else {
+            Archive::from_har(&data)?
+        };
+
+        log!(info, "{} records indexed from {}", archive.records.len(), path.display());
+
+        Ok(archive)
+    }
+
+    fn from_warc(mut data: &[u8]) -> Result<Archive> {
+        let mut archive = Archive::default();
+
+        loop {
+            while data.starts_with(b"\r\n") || data.starts_with(b"\n") {
+                data = &data[if data[0] == b'\r' { 2 } else { 1 }..];
+            }
+
+            if data.is_empty() {
+                break;
+            }
+
+            let (headers, rest) = split_head(data)
+                .ok_or_else(|| anyhow!("truncated WARC record header"))?;
+
+            let headers = parse_headers(headers);
+            let len = headers.get("content-length")
+                             .and_then(|v| v.parse::<usize>().ok())
+                             .ok_or_else(|| anyhow!("WARC record without Content-Length"))?;
+
+            if rest.len() < len {
+                bail!("truncated WARC record block");
+            }
+
+            let (block, rest) = rest.split_at(len);
+            data = rest;
+
+            let uri = match headers.get("warc-target-uri") {
+                Some(uri) => uri.trim_start_matches('<').trim_end_matches('>'),
+                None => continue,
+            };
+
+            let record = match headers.get("warc-type").map(String::as_str) {
+                Some("response") => match parse_http_response(block) {
+                    Some(record) => record,
+                    None => {
+                        log!(warn, "cannot parse HTTP response for {}", uri);
+                        continue;
+                    }
+                },
+                Some("resource") => Record {
+                    status: 200,
+                    mime: headers.get("content-type").cloned(),
+                    location: None,
+                    data: block.to_vec(),
+                },
+                _ => continue,
+            };
+
+            archive.insert(uri, record);
+        }
+
+        Ok(archive)
+    }
+
+    fn from_har(data: &[u8]) -> Result<Archive> {
+        let har: Value = serde_json::from_slice(data)
+            .map_err(|e| anyhow!("neither WARC nor HAR: {}", e))?;
+
+        let entries = har.pointer("/log/entries")
+                         .and_then(Value::as_array)
+                         .ok_or_else(|| anyhow!("HAR without log.entries"))?;
+
+        let mut archive = Archive::default();
+
+        for entry in entries {
+            let uri = match entry.pointer("/request/url").and_then(Value::as_str) {
+                Some(uri) => uri,
+                None => continue,
+            };
+
+            let response = &entry["response"];
+            let content = &response["content"];
+
+            let text = content["text"].as_str().unwrap_or_default();
+            let data = match content["encoding"].as_str() {
+                Some("base64") => base64::decode(text)?,
+                _ => text.as_bytes().to_vec(),
+            };
+
+            let location = response["redirectURL"]
+                .as_str()
+                .filter(|v| !v.is_empty())
+                .map(ToOwned::to_owned);
+
+            archive.insert(uri, Record {
+                status: response["status"].as_i64().unwrap_or(0) as i32,
+                mime: content["mimeType"].as_str().filter(|v| !v.is_empty()).map(ToOwned::to_owned),
+                location,
+                data,
+            });
+        }
+
+        Ok(archive)
+    }
+
+    fn insert(&mut self, uri: &str, record: Record) {
+        match Url::parse(uri) {
+            // first capture of a URL wins, later ones are usually retries
+            Ok(url) => { self.records.entry(key(&url)).or_insert(record); }
+            Err(_) => { log!(debug, "skipping archived record with bad URL {}", uri); }
+        }
+    }
+
+    /// Final URL, MIME type and body, `None` if the URL was never captured;
+    /// archived redirects are followed
+    pub fn get(&self, url: &Url, max_redirects: usize) -> Option<Result<Archived>> {
+        let mut url = url.clone();
+        let mut redirects = vec![];
+
+        for _ in 0..=max_redirects {
+            let record = self.records.get(&key(&url))?;
+
+            match record.status {
+                200..=299 => {
+                    log!(info, "serving {} from archive", url);
+                    return Some(Ok(Archived {
+                        url,
+                        mime: record.mime.clone(),
+                        data: record.data.clone(),
+                        redirects,
+                    }));
+                }
+                300..=399 if record.location.is_some() => {
+                    let location = record.location.clone().unwrap_or_default();
+                    let next = match url.join(&location) {
+                        Ok(next) => next,
+                        Err(e) => return Some(Err(e.into())),
+                    };
+                    log!(debug, "archived redirect to {}", next);
+                    redirects.push((std::mem::replace(&mut url, next), record.status, location));
+                }
+                status => return Some(Err(LoadError::Status(status).into())),
+            }
+        }
+
+        Some(Err(LoadError::Fetch(format!("more than {} archived redirects", max_redirects)).into()))
+    }
+}
+
+fn key(url: &Url) -> String {
+    let mut url = url.clone();
+    url.set_fragment(None);
+    url.to_string()
+}
+
+// Splits at the first empty line; tolerates both CRLF and bare LF
+fn split_head(data: &[u8]) -> Option<(&[u8], &[u8])> {
+    data.windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .map(|i| (&data[..i], &data[i + 4..]))
+        .or_else(|| data.windows(2)
+                        .position(|w| w == b"\n\n")
+                        .map(|i| (&data[..i], &data[i + 2..])))
+}
+
+fn parse_headers(head: &[u8]) -> HashMap<String, String> {
+    String::from_utf8_lossy(head)
+        .lines()
+        .filter_map(|line| {
+            let mut kv = line.splitn(2, ':');
+            Some((kv.next()?.trim().to_lowercase(), kv.next()?.trim().to_owned()))
+        })
+        .collect()
+}
+
+fn parse_http_response(block: &[u8]) -> Option<Record> {
+    let (head, body) = split_head(block)?;
+    let head_str = String::from_utf8_lossy(head);
+
+    let status = head_str.lines()
+                         .next()?
+                         .split_whitespace()
+                         .nth(1)?
+                         .parse()
+                         .ok()?;
+
+    let headers = parse_headers(head);
+
+    let data = match headers.get("transfer-encoding") {
+        Some(te) if te.eq_ignore_ascii_case("chunked") => dechunk(body)?,
+        _ => body.to_vec(),
+    };
+
+    // WARC keeps bodies the way they came over the wire
+    let data = match headers.get("content-encoding") {
+        Some(ce) => compress::decode(data, ce).ok()?,
+        None => data,
+    };
+
+    Some(Record {
+        status,
+        mime: headers.get("content-type").cloned(),
+        location: headers.get("location").cloned(),
+        data,
+    })
+}
+
+fn dechunk(mut body: &[u8]) -> Option<Vec<u8>> {
+    let mut data = Vec::new();
+
+    loop {
+        let eol = body.windows(2).position(|w| w == b"\r\n")?;
+        let size = std::str::from_utf8(&body[..eol]).ok()?;
+        let size = usize::from_str_radix(size.split(';').next()?.trim(), 16).ok()?;
+
+        body = &body[eol + 2..];
+
+        if size == 0 {
+            return Some(data);
+        }
+
+        data.extend_from_slice(body.get(..size)?);
+        body = body.get(size + 2..)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WARC: &str = "WARC/1.0\r\n\
+        WARC-Type: warcinfo\r\n\
+        Content-Length: 9\r\n\
+        \r\n\
+        format: 1\r\n\r\n\
+        WARC/1.0\r\n\
+        WARC-Type: response\r\n\
+        WARC-Target-URI: https://example.com/\r\n\
+        Content-Length: 62\r\n\
+        \r\n\
+        HTTP/1.1 301 Moved Permanently\r\n\
+        Location: /index.html\r\n\
+        \r\n\
+        moved\r\n\r\n\
+        WARC/1.0\r\n\
+        WARC-Type: response\r\n\
+        WARC-Target-URI: <https://example.com/index.html>\r\n\
+        Content-Length: 86\r\n\
+        \r\n\
+        HTTP/1.1 200 OK\r\n\
+        Content-Type: text/html\r\n\
+        Transfer-Encoding: chunked\r\n\
+        \r\n\
+        4\r\n<p>1\r\n\
+        0\r\n\
+        \r\n\r\n\r\n";
+
+    const HAR: &str = r#"{ "log": { "entries": [
+        { "request": { "url": "https://example.com/a.css" },
+          "response": { "status": 200, "redirectURL": "",
+                        "content": { "mimeType": "text/css", "text": "p {}" } } },
+        { "request": { "url": "https://example.com/i.gif" },
+          "response": { "status": 200,
+                        "content": { "mimeType": "image/gif", "text": "R0lGODdh", "encoding": "base64" } } },
+        { "request": { "url": "https://example.com/gone" },
+          "response": { "status": 404, "content": {} } }
+    ] } }"#;
+
+    fn get(archive: &Archive, url: &str) -> Option<Result<(Option<String>, Vec<u8>)>> {
+        archive.get(&Url::parse(url).unwrap(), 10)
+               .map(|res| res.map(|a| (a.mime, a.data)))
+    }
+
+    #[test]
+    fn warc_records() {
+        let archive = Archive::from_warc(WARC.as_bytes()).unwrap();
+
+        let (mime, data) = get(&archive, "https://example.com/#top").unwrap().unwrap();
+        assert_eq!(mime.as_deref(), Some("text/html"));
+        assert_eq!(data, b"<p>1");
+
+        let archived = archive.get(&Url::parse("https://example.com/").unwrap(), 10).unwrap().unwrap();
+        assert_eq!(archived.url.as_str(), "https://example.com/index.html");
+        assert_eq!(archived.redirects, [(Url::parse("https://example.com/").unwrap(), 301, "/index.html".to_owned())]);
+
+        assert!(get(&archive, "https://example.com/other").is_none());
+    }
+
+    #[test]
+    fn har_entries() {
+        let archive = Archive::from_har(HAR.as_bytes()).unwrap();
+
+        let (mime, data) = get(&archive, "https://example.com/a.css").unwrap().unwrap();
+        assert_eq!((mime.as_deref(), data.as_slice()), (Some("text/css"), &b"p {}"[..]));
+
+        let (_, data) = get(&archive, "https://example.com/i.gif").unwrap().unwrap();
+        assert_eq!(data, b"GIF87a");
+
+        assert!(get(&archive, "https://example.com/gone").unwrap().is_err());
+    }
+}
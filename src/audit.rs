@@ -0,0 +1,159 @@
+use crate::utils;
+
+use anyhow::{Result, bail};
+use once_cell::sync::Lazy;
+use serde_json::json;
+use url::Url;
+
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::sync::Mutex;
+
+static RESOURCES: Lazy<Mutex<Vec<Resource>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Table,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "table" => Ok(Format::Table),
+            "json" => Ok(Format::Json),
+            _ => bail!("unknown format `{}` (use `table` or `json`)", s),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Resource {
+    handler: String,
+    element: String,
+    attribute: String,
+    url: String,
+    // why a filter leaves it out
+    filtered: Option<String>,
+}
+
+/// Remembers a resource `load_file()` was asked for during --list
+pub fn record(href: &str, url: &Url, filtered: Option<String>) {
+    if url.scheme() == "data" {
+        return;
+    }
+
+    let resource = match utils::initiator() {
+        Some(i) => Resource {
+            attribute: attribute(&i.attributes, href),
+            handler: i.handler,
+            element: i.element,
+            url: url.to_string(),
+            filtered,
+        },
+        None => Resource {
+            handler: "-".to_owned(),
+            element: "-".to_owned(),
+            attribute: "-".to_owned(),
+            url: url.to_string(),
+            filtered,
+        },
+    };
+
+    let mut resources = RESOURCES.lock().expect("cannot reach resource list");
+
+    if !resources.contains(&resource) {
+        resources.push(resource);
+    }
+}
+
+// Attribute the URL came from; `#text` is for <style> contents
+fn attribute(attributes: &[(String, String)], href: &str) -> String {
+    attributes.iter()
+              .find(|(_, v)| v == href)
+              .or_else(|| attributes.iter().find(|(_, v)| v.contains(href)))
+              .map_or_else(|| "#text".to_owned(), |(k, _)| k.to_owned())
+}
+
+/// `skip` maps names of disabled handlers to the flag that disabled them
+pub fn print(skip: &[(&str, &str)], format: Format) -> Result<()> {
+    let mut resources = std::mem::take(&mut *RESOURCES.lock().expect("cannot reach resource list"));
+
+    // handlers run in parallel so the order is arbitrary anyway
+    resources.sort_by(|a, b| (&a.handler, &a.url).cmp(&(&b.handler, &b.url)));
+
+    let status = |r: &Resource| {
+        skip.iter()
+            .find(|(name, _)| *name == r.handler)
+            .map(|(_, flag)| flag.to_string())
+            .or_else(|| r.filtered.clone())
+            .map_or_else(|| "inline".to_owned(), |why| format!("skip ({})", why))
+    };
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+
+    match format {
+        Format::Json => {
+            let list = resources.iter()
+                                .map(|r| json!({
+                                    "element": r.element,
+                                    "attribute": r.attribute,
+                                    "url": r.url,
+                                    "handler": r.handler,
+                                    "skipped": status(r) != "inline",
+                                    "status": status(r),
+                                }))
+                                .collect::<Vec<_>>();
+
+            serde_json::to_writer_pretty(&mut out, &list)?;
+            writeln!(out)?;
+        }
+        Format::Table => {
+            let rows = resources.iter()
+                                .map(|r| [r.handler.as_str(), r.element.as_str(), r.attribute.as_str(), r.url.as_str()])
+                                .collect::<Vec<_>>();
+
+            let header = ["HANDLER", "ELEMENT", "ATTRIBUTE", "URL"];
+            let mut width = header.map(str::len);
+
+            for row in &rows {
+                for (w, col) in width.iter_mut().zip(row) {
+                    *w = (*w).max(col.len());
+                }
+            }
+
+            writeln!(out, "{:w0$}  {:w1$}  {:w2$}  {:w3$}  STATUS",
+                     header[0], header[1], header[2], header[3],
+                     w0 = width[0], w1 = width[1], w2 = width[2], w3 = width[3])?;
+
+            for (row, r) in rows.iter().zip(&resources) {
+                writeln!(out, "{:w0$}  {:w1$}  {:w2$}  {:w3$}  {}",
+                         row[0], row[1], row[2], row[3], status(r),
+                         w0 = width[0], w1 = width[1], w2 = width[2], w3 = width[3])?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn guess_attribute() {
+        let attributes = vec![
+            ("class".to_owned(), "logo".to_owned()),
+            ("src".to_owned(), "i.png".to_owned()),
+            ("style".to_owned(), "background: url(bg.png)".to_owned()),
+        ];
+
+        assert_eq!(attribute(&attributes, "i.png"), "src");
+        assert_eq!(attribute(&attributes, "bg.png"), "style");
+        assert_eq!(attribute(&attributes, "import.css"), "#text");
+    }
+}
@@ -0,0 +1,180 @@
+use chardetng::EncodingDetector;
+use encoding_rs::{Encoding, UTF_8};
+use html5ever::{interface::QualName, local_name, namespace_url, ns};
+use kuchiki::{Attribute, ExpandedName, NodeRef};
+
+// <meta charset=...> or <meta http-equiv=Content-Type content="...; charset=...">
+regex!(static RE_META, r#"(?xi)
+
+        <meta\s[^>]*?
+        charset\s*=\s*["']?     # charset= or content="text/html; charset=
+        (?P<charset>[\w:.-]+)   # label
+
+"#);
+
+// has to be the very first thing in a stylesheet
+regex!(static RE_CSS_CHARSET, r#"^@charset\s+"(?P<charset>[^"]+)"\s*;"#);
+
+// the HTML spec only looks that far for a <meta>
+const PRESCAN: usize = 1024;
+
+/// HTML as UTF-8: BOM, HTTP `charset`, `<meta>`, then a guess
+pub fn decode_html(data: &[u8], content_type: &str) -> String {
+    let meta = || {
+        let head = String::from_utf8_lossy(&data[..data.len().min(PRESCAN)]);
+
+        RE_META.captures(&head)
+               .and_then(|cap| label(&cap["charset"]))
+               // a page can't be UTF-16 if <meta> is readable as ASCII
+               .map(|e| e.output_encoding())
+    };
+
+    decode(data, content_type, meta)
+}
+
+/// Stylesheets and scripts as UTF-8: BOM, HTTP `charset`, CSS `@charset`, then a guess;
+/// `@charset` goes away, the text is not in that encoding anymore
+pub fn decode_text(data: &[u8], content_type: &str) -> String {
+    let at_charset = || {
+        RE_CSS_CHARSET.captures(&String::from_utf8_lossy(&data[..data.len().min(PRESCAN)]))
+                      .and_then(|cap| label(&cap["charset"]))
+                      .map(|e| e.output_encoding())
+    };
+
+    let text = decode(data, content_type, at_charset);
+
+    match RE_CSS_CHARSET.find(&text) {
+        Some(m) => text[m.end()..].trim_start_matches(['\r', '\n']).to_owned(),
+        None => text,
+    }
+}
+
+fn decode(data: &[u8], content_type: &str, declared: impl FnOnce() -> Option<&'static Encoding>) -> String {
+    let (encoding, bom) = match Encoding::for_bom(data) {
+        Some((encoding, len)) => (encoding, len),
+        None => {
+            let encoding = http_charset(content_type)
+                .or_else(declared)
+                .unwrap_or_else(|| guess(data));
+            (encoding, 0)
+        }
+    };
+
+    if encoding != UTF_8 {
+        log!(debug, "decoding {} bytes as {}", data.len(), encoding.name());
+    }
+
+    encoding.decode_without_bom_handling(&data[bom..]).0.into_owned()
+}
+
+/// The page is always written out as UTF-8, whatever it declared has to follow
+pub fn declare_utf8(html: &NodeRef) {
+    // <meta http-equiv="Content-Type" content="text/html; charset=..."> is the old way
+    html.select("meta[http-equiv]")
+        .map_or(vec![], |v| v.collect())
+        .into_iter()
+        .filter(|m| m.attributes
+                     .borrow()
+                     .get("http-equiv")
+                     .is_some_and(|v| v.eq_ignore_ascii_case("content-type")))
+        .for_each(|m| m.as_node().detach());
+
+    let metas = html.select("meta[charset]")
+                    .map_or(vec![], |v| v.collect());
+
+    if let Some((meta, rest)) = metas.split_first() {
+        meta.attributes.borrow_mut().insert("charset", "utf-8".to_owned());
+        rest.iter().for_each(|m| m.as_node().detach());
+        return;
+    }
+
+    if let Ok(head) = html.select_first("head") {
+        let elm = NodeRef::new_element(
+            QualName::new(None, ns!(html), local_name!("meta")),
+            vec![(
+                ExpandedName::new("", "charset"),
+                Attribute {
+                    prefix: None,
+                    value: "utf-8".to_owned(),
+                },
+            )]);
+
+        log!(debug, "prepending {}", elm.to_string());
+
+        head.as_node().prepend(elm);
+    }
+}
+
+fn http_charset(content_type: &str) -> Option<&'static Encoding> {
+    content_type.split(';')
+                .skip(1)
+                .filter_map(|param| param.split_once('='))
+                .find(|(k, _)| k.trim().eq_ignore_ascii_case("charset"))
+                .and_then(|(_, v)| label(v.trim().trim_matches('"')))
+}
+
+fn label(label: &str) -> Option<&'static Encoding> {
+    Encoding::for_label(label.trim().as_bytes())
+}
+
+// valid UTF-8 is taken as such, anything else is up to the detector
+fn guess(data: &[u8]) -> &'static Encoding {
+    if std::str::from_utf8(data).is_ok() {
+        return UTF_8;
+    }
+
+    let mut detector = EncodingDetector::new();
+    detector.feed(data, true);
+    detector.guess(None, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn html_charsets() {
+        // "Привет" in windows-1251
+        let cp1251 = b"\xcf\xf0\xe8\xe2\xe5\xf2";
+        let page = |head: &str| [head.as_bytes(), cp1251].concat();
+
+        assert_eq!(decode_html(&page(""), "text/html; charset=windows-1251"), "Привет");
+        assert_eq!(decode_html(&page(r#"<meta charset="windows-1251">"#), "text/html"), r#"<meta charset="windows-1251">Привет"#);
+        assert_eq!(decode_html(&page(r#"<meta http-equiv="Content-Type" content="text/html; charset=cp1251">"#), ""),
+                   r#"<meta http-equiv="Content-Type" content="text/html; charset=cp1251">Привет"#);
+
+        // HTTP wins over <meta>, BOM wins over both
+        assert_eq!(decode_html(b"<meta charset=utf-8>\xe9", "text/html; charset=iso-8859-1"), "<meta charset=utf-8>é");
+        assert_eq!(decode_html("\u{feff}é".as_bytes(), "text/html; charset=iso-8859-1"), "é");
+    }
+
+    #[test]
+    fn meta_utf8() {
+        use kuchiki::traits::*;
+
+        let fix = |html: &str| {
+            let html = kuchiki::parse_html().one(html);
+            declare_utf8(&html);
+            html.select_first("head").unwrap().as_node().to_string()
+        };
+
+        assert_eq!(fix("<title>t</title>"), r#"<head><meta charset="utf-8"><title>t</title></head>"#);
+        assert_eq!(fix(r#"<meta http-equiv="content-type" content="text/html; charset=cp1251"><meta charset="cp1251">"#),
+                   r#"<head><meta charset="utf-8"></head>"#);
+    }
+
+    #[test]
+    fn text_charsets() {
+        assert_eq!(decode_text(b"@charset \"iso-8859-1\";\np::before { content: '\xe9' }", "text/css"),
+                   "p::before { content: 'é' }");
+        assert_eq!(decode_text("p { content: 'é' }".as_bytes(), "text/css"), "p { content: 'é' }");
+
+        // Shift_JIS, no hints at all
+        let sjis = b"/* \x82\xb1\x82\xea\x82\xcd\x93\xfa\x96\x7b\x8c\xea\x82\xcc\x83\x58\x83\x5e\x83\x43\x83\x8b\x83\x56\x81\x5b\
+                     \x83\x67\x82\xc5\x82\xb7\x81\x42\x95\xb6\x8e\x9a\x83\x52\x81\x5b\x83\x68\x82\xf0\x8e\xa9\x93\xae\x82\xc5\
+                     \x94\xbb\x92\xe8\x82\xb5\x82\xdc\x82\xb7\x81\x42 */";
+
+        assert_eq!(decode_text(sjis, "application/javascript"),
+                   "/* これは日本語のスタイルシートです。文字コードを自動で判定します。 */");
+    }
+}
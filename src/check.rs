@@ -0,0 +1,182 @@
+use crate::charset;
+use crate::handler;
+use crate::srcset;
+use crate::utils;
+
+use anyhow::{Result, bail};
+use kuchiki::{NodeRef, traits::*};
+use url::Url;
+
+// quoted absolute or protocol-relative URLs in scripts
+regex!(static RE_JS_URL, r#"(?x)
+
+        ["'`]                           # opening quote
+        (?P<url>(?:https?:)?//[^"'`\s]+)  # http://, https:// or //
+        ["'`]                           # closing quote
+
+"#);
+
+// attributes holding a single URL (`href` is special cased)
+const URL_ATTRS: &[&str] = &["src", "poster", "data", "background"];
+
+// <link rel=...> values which do not load anything
+const NAV_RELS: &[&str] = &[
+    "alternate", "author", "bookmark", "canonical", "external", "help",
+    "license", "me", "next", "prev", "search", "shortlink",
+];
+
+// schemes that never reach the network
+const LOCAL_SCHEMES: &[&str] = &["data:", "blob:", "about:", "javascript:", "mailto:", "tel:"];
+
+#[derive(Debug, PartialEq)]
+pub struct Reference {
+    pub element: String,
+    pub attribute: String,
+    pub url: String,
+}
+
+/// `inline check FILE`: fails if the document still points anywhere
+pub fn run(input: &Url) -> Result<()> {
+    let resp = utils::load_url(input)?;
+    let html = kuchiki::parse_html().one(charset::decode_html(&resp.data, &resp.mime));
+
+    let refs = references(&html);
+
+    for r in &refs {
+        println!("<{} {}> {}", r.element, r.attribute, r.url);
+    }
+
+    if !refs.is_empty() {
+        bail!("{} is not self-contained: {} external reference(s)", input, refs.len());
+    }
+
+    log!(info, "{} is self-contained", input);
+
+    Ok(())
+}
+
+pub fn references(html: &NodeRef) -> Vec<Reference> {
+    let mut refs = vec![];
+
+    for node in html.inclusive_descendants().elements() {
+        let element = node.name.local.to_string();
+        let attrs = node.attributes.borrow();
+
+        let mut push = |attribute: &str, url: &str| {
+            let url = url.trim();
+
+            if is_external(url) {
+                refs.push(Reference {
+                    element: element.clone(),
+                    attribute: attribute.to_owned(),
+                    url: url.to_owned(),
+                });
+            }
+        };
+
+        for (name, attr) in &attrs.map {
+            let name = &*name.local;
+            let value = attr.value.as_str();
+
+            match name {
+                "href" if is_navigation(&element, attrs.get("rel")) => {}
+                "href" => push(name, value),
+                "srcset" | "imagesrcset" => {
+                    srcset::parse(value)
+                        .iter()
+                        .for_each(|c| push(name, &c.url));
+                }
+                "style" => {
+                    handler::css::references(value)
+                        .into_iter()
+                        .for_each(|url| push(name, url));
+                }
+                _ if URL_ATTRS.contains(&name) => push(name, value),
+                _ => {}
+            }
+        }
+
+        match element.as_str() {
+            "style" => {
+                handler::css::references(&node.text_contents())
+                    .into_iter()
+                    .for_each(|url| push("#text", url));
+            }
+            "script" if attrs.get("type") != Some("application/ld+json") => {
+                RE_JS_URL.captures_iter(&node.text_contents())
+                         .filter_map(|cap| cap.name("url"))
+                         .map(|m| m.as_str())
+                         // XML namespaces are identifiers, not resources
+                         .filter(|url| !url.starts_with("http://www.w3.org/"))
+                         .for_each(|url| push("#text", url));
+            }
+            _ => {}
+        }
+    }
+
+    refs
+}
+
+fn is_navigation(element: &str, rel: Option<&str>) -> bool {
+    match element {
+        "a" | "area" => true,
+        "link" => rel.is_some_and(|rel| {
+            rel.split_whitespace()
+               .all(|r| NAV_RELS.contains(&r.to_lowercase().as_str()))
+        }),
+        _ => false,
+    }
+}
+
+fn is_external(url: &str) -> bool {
+    let lower = url.to_lowercase();
+
+    !url.is_empty()
+        && !url.starts_with('#')
+        && !LOCAL_SCHEMES.iter().any(|s| lower.starts_with(s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_references() {
+        let html = kuchiki::parse_html().one(r##"
+            <html>
+              <head>
+                <base href="https://example.com/">
+                <link rel="canonical" href="https://example.com/page">
+                <link rel="preconnect" href="https://fonts.gstatic.com">
+                <style>@import 'print.css'; p { background: url(data:image/png;base64,AAAA) }</style>
+                <script>fetch("https://api.example.com/v1"); let ns = "http://www.w3.org/2000/svg";</script>
+              </head>
+              <body>
+                <a href="https://example.com/about">about</a>
+                <img src="data:image/gif;base64,R0lGODdh" srcset="a.png 1x, https://cdn.example.com/a@2x.png 2x">
+                <img srcset="data:image/png;base64,iVBO,Rw0K 1x, b.png 2x">
+                <video poster="//cdn.example.com/poster.jpg"></video>
+                <i style="background: url('bg.png')"></i>
+                <svg><use href="#icon"></use></svg>
+              </body>
+            </html>
+        "##);
+
+        let found = references(&html)
+            .into_iter()
+            .map(|r| format!("{} {} {}", r.element, r.attribute, r.url))
+            .collect::<Vec<_>>();
+
+        assert_eq!(found, [
+            "base href https://example.com/",
+            "link href https://fonts.gstatic.com",
+            "style #text print.css",
+            "script #text https://api.example.com/v1",
+            "img srcset a.png",
+            "img srcset https://cdn.example.com/a@2x.png",
+            "img srcset b.png",
+            "video poster //cdn.example.com/poster.jpg",
+            "i style bg.png",
+        ]);
+    }
+}
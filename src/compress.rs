@@ -0,0 +1,80 @@
+use crate::report::LoadError;
+
+use flate2::read::{DeflateDecoder, MultiGzDecoder, ZlibDecoder};
+
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// What goes into `Accept-Encoding`
+pub const ACCEPT: &str = "gzip, deflate, br";
+
+/// Undoes `Content-Encoding`; codings are listed in the order they were applied
+pub fn decode(data: Vec<u8>, content_encoding: &str) -> Result<Vec<u8>, LoadError> {
+    content_encoding
+        .split(',')
+        .map(|c| c.trim().to_lowercase())
+        .filter(|c| !c.is_empty())
+        .rev()
+        .try_fold(data, |data, coding| {
+            let decoded = match coding.as_str() {
+                "identity" => return Ok(data),
+                "gzip" | "x-gzip" => read(MultiGzDecoder::new(&data[..])),
+                // should be zlib wrapped, some servers send it raw
+                "deflate" => read(ZlibDecoder::new(&data[..]))
+                                 .or_else(|_| read(DeflateDecoder::new(&data[..]))),
+                "br" => read(brotli_decompressor::Decompressor::new(&data[..], 4096)),
+                _ => return Err(LoadError::Decode(format!("unsupported content encoding `{}`", coding))),
+            };
+
+            decoded.map_err(|e| LoadError::Decode(format!("{}: {}", coding, e)))
+        })
+}
+
+fn read(mut r: impl Read) -> std::io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    r.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// `style.css.br` or `style.css.gz` next to a missing `style.css`, with its coding
+pub fn sibling(path: &Path) -> Option<(PathBuf, &'static str)> {
+    [("br", "br"), ("gz", "gzip")]
+        .iter()
+        .map(|(ext, coding)| {
+            let mut name = path.as_os_str().to_owned();
+            name.push(".");
+            name.push(ext);
+            (PathBuf::from(name), *coding)
+        })
+        .find(|(path, _)| path.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use flate2::{Compression, write::GzEncoder, write::ZlibEncoder};
+    use std::io::Write;
+
+    #[test]
+    fn content_encodings() {
+        let css = b"p { color: red }".to_vec();
+
+        let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+        gz.write_all(&css).unwrap();
+        let gz = gz.finish().unwrap();
+
+        let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+        zlib.write_all(&gz).unwrap();
+        let gz_zlib = zlib.finish().unwrap();
+
+        // brotli stream with one uncompressed 16 byte meta-block
+        let br = [&b"\xf0\x00\x10"[..], &css, b"\x03"].concat();
+
+        assert_eq!(decode(gz, "gzip").unwrap(), css);
+        assert_eq!(decode(gz_zlib, "gzip, deflate").unwrap(), css);
+        assert_eq!(decode(br, "br").unwrap(), css);
+        assert_eq!(decode(css.clone(), "identity").unwrap(), css);
+        assert!(decode(css, "zstd").is_err());
+    }
+}
@@ -0,0 +1,217 @@
+use crate::OPT;
+use crate::report::LoadError;
+use crate::utils::Response;
+
+use anyhow::{Result, anyhow, bail};
+use once_cell::sync::OnceCell;
+use regex::Regex;
+use url::Url;
+
+use std::str::FromStr;
+
+// set once in main(), files the user injected are not held to --max-size
+pub static INJECTED: OnceCell<Vec<Url>> = OnceCell::new();
+
+// served by a program, their extension says nothing about what comes back
+const DYNAMIC: &[&str] = &["php", "asp", "aspx", "ashx", "jsp", "jspx", "cgi", "pl", "py", "rb", "cfm", "do", "action"];
+
+/// Glob (`*.mp4`, `*://ads.example.com/*`) or, with a `re:` prefix, a regex
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    source: String,
+    re: Regex,
+    // globs look at the URL without query and fragment, regexes at all of it
+    glob: bool,
+}
+
+impl FromStr for Pattern {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (re, glob) = match s.strip_prefix("re:") {
+            Some(re) => (re.to_owned(), false),
+            None => (glob_to_regex(s), true),
+        };
+
+        Ok(Pattern {
+            source: s.to_owned(),
+            re: Regex::new(&re).map_err(|e| anyhow!("bad pattern `{}`: {}", s, e))?,
+            glob,
+        })
+    }
+}
+
+impl Pattern {
+    fn matches(&self, text: &str) -> bool {
+        self.re.is_match(text)
+    }
+
+    fn matches_url(&self, url: &Url) -> bool {
+        if !self.glob {
+            return self.matches(url.as_str());
+        }
+
+        let mut url = url.clone();
+        url.set_query(None);
+        url.set_fragment(None);
+
+        self.matches(url.as_str())
+    }
+}
+
+// `*` is any run of characters, `?` a single one, the rest is literal
+fn glob_to_regex(glob: &str) -> String {
+    let mut re = String::from("(?i)^");
+
+    for c in glob.chars() {
+        match c {
+            '*' => re.push_str(".*"),
+            '?' => re.push('.'),
+            c => re.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+
+    re.push('$');
+    re
+}
+
+/// `200K`, `1.5M`, `1G` or plain bytes
+pub fn parse_size(s: &str) -> Result<usize> {
+    let s = s.trim();
+    let (num, unit) = s.find(|c: char| c.is_ascii_alphabetic())
+                       .map_or((s, ""), |i| s.split_at(i));
+
+    let unit = match unit.to_uppercase().trim_end_matches(['B', 'I']) {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        _ => bail!("unknown size unit in `{}` (use K, M or G)", s),
+    };
+
+    let num: f64 = num.trim().parse().map_err(|_| anyhow!("bad size `{}`", s))?;
+
+    Ok((num * unit as f64) as usize)
+}
+
+/// --include/--exclude and type rules that can be decided before fetching
+pub fn check_url(url: &Url) -> Result<(), LoadError> {
+    // already inline
+    if url.scheme() == "data" {
+        return Ok(());
+    }
+
+    if let Some(p) = OPT.exclude.iter().find(|p| p.matches_url(url)) {
+        return Err(LoadError::Filtered(format!("--exclude {}", p.source)));
+    }
+
+    if !OPT.include.is_empty() && !OPT.include.iter().any(|p| p.matches_url(url)) {
+        return Err(LoadError::Filtered("no --include matches".to_owned()));
+    }
+
+    // only a telling extension is worth skipping the fetch for,
+    // check_response() has the real type of the rest
+    match guess_type(url) {
+        Some(mime) => check_type(mime),
+        None => Ok(()),
+    }
+}
+
+/// --max-size for a resource the page refers to, `None` if there is no limit
+pub fn max_size(url: &Url) -> Option<usize> {
+    match INJECTED.get() {
+        Some(injected) if injected.contains(url) => None,
+        _ => OPT.max_size,
+    }
+}
+
+/// Type and size rules against what actually came back
+pub fn check_response(resp: &Response, max_size: Option<usize>) -> Result<(), LoadError> {
+    if resp.url.scheme() == "data" {
+        return Ok(());
+    }
+
+    check_size(resp.data.len(), max_size)?;
+    check_type(&resp.mime)
+}
+
+/// --max-size, also for a `Content-Length` before the body is downloaded
+pub fn check_size(size: usize, max_size: Option<usize>) -> Result<(), LoadError> {
+    match max_size {
+        Some(max) if size > max => Err(LoadError::Filtered(format!("{} bytes is over --max-size", size))),
+        _ => Ok(()),
+    }
+}
+
+// type of a static file by its extension, `None` for `/img.php` or `/images/`
+fn guess_type(url: &Url) -> Option<&'static str> {
+    let name = url.path_segments()?.next_back()?;
+    let ext = name.rsplit_once('.')?.1.to_lowercase();
+
+    if DYNAMIC.contains(&ext.as_str()) {
+        return None;
+    }
+
+    mime_guess::from_ext(&ext).first_raw()
+}
+
+fn check_type(mime: &str) -> Result<(), LoadError> {
+    // `text/css; charset=utf-8` is still `text/css`
+    let mime = mime.split(';').next().unwrap_or_default().trim();
+
+    if let Some(p) = OPT.exclude_type.iter().find(|p| p.matches(mime)) {
+        return Err(LoadError::Filtered(format!("--exclude-type {}", p.source)));
+    }
+
+    if !OPT.include_type.is_empty() && !OPT.include_type.iter().any(|p| p.matches(mime)) {
+        return Err(LoadError::Filtered(format!("{} matches no --include-type", mime)));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patterns() {
+        let url = |s: &str| Url::parse(s).unwrap();
+
+        let mp4 = "*.mp4".parse::<Pattern>().unwrap();
+        assert!(mp4.matches_url(&url("https://example.com/media/intro.MP4?autoplay=1")));
+        assert!(!mp4.matches_url(&url("https://example.com/intro.mp4.html")));
+
+        let ads = "*://ads.example.com/*".parse::<Pattern>().unwrap();
+        assert!(ads.matches_url(&url("http://ads.example.com/banner.gif")));
+        assert!(!ads.matches_url(&url("http://example.com/ads.example.com/")));
+
+        let re = r"re:^https://cdn\.example\.com/.*\?v=\d+$".parse::<Pattern>().unwrap();
+        assert!(re.matches_url(&url("https://cdn.example.com/app.js?v=12")));
+
+        let images = "image/*".parse::<Pattern>().unwrap();
+        assert!(images.matches("image/svg+xml"));
+        assert!(!images.matches("video/mp4"));
+
+        assert!("re:(".parse::<Pattern>().is_err());
+    }
+
+    #[test]
+    fn guessed_types() {
+        let guess = |s: &str| guess_type(&Url::parse(s).unwrap());
+
+        assert_eq!(guess("https://example.com/a/logo.PNG?v=2"), Some("image/png"));
+        assert_eq!(guess("https://example.com/style.css"), Some("text/css"));
+        assert_eq!(guess("https://example.com/img.php?id=7"), None);
+        assert_eq!(guess("https://example.com/images/"), None);
+        assert_eq!(guess("https://example.com/v1.2/avatar"), None);
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("512").unwrap(), 512);
+        assert_eq!(parse_size("200K").unwrap(), 200 * 1024);
+        assert_eq!(parse_size("1.5MiB").unwrap(), 1536 * 1024);
+        assert!(parse_size("10 parsecs").is_err());
+    }
+}
@@ -4,7 +4,7 @@ use crate::OPT;
 use html5ever::{interface::QualName, local_name, namespace_url, ns};
 use kuchiki::{Attribute, ElementData, ExpandedName, NodeDataRef, NodeRef};
 
-pub const TAG: SH = (r#"head"#, base_href);
+pub const TAG: SH = ("base", r#"head"#, base_href);
 
 fn base_href(node: &NodeDataRef<ElementData>) {
     if let Ok(tag) = node.as_node().select_first("base[href]") {
@@ -29,6 +29,6 @@ fn base_href(node: &NodeDataRef<ElementData>) {
 
             node.as_node().append(elm);
         }
-        _ => return
+        _ => {}
     }
 }
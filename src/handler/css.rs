@@ -1,10 +1,13 @@
-use crate::SH;
+use crate::{OPT, SH};
+use crate::srcset::{self, Candidate, Policy};
 use crate::utils;
 
 use kuchiki::{ElementData, ExpandedName, Attribute, NodeDataRef, NodeRef};
 use rayon::prelude::*;
-use regex::Captures;
+use regex::{Captures, Regex};
+use url::Url;
 
+use std::cell::RefCell;
 use std::collections::{HashMap, HashSet};
 use std::sync::RwLock;
 
@@ -30,42 +33,232 @@ regex!(static RE_IMPORT, r#"(?x)
 
 "#);
 
+// `image-set(` and `-webkit-image-set(`, arguments are found by hand as they nest
+// not part of a longer name such as `-moz-image-set(`
+regex!(static RE_IMAGE_SET, r#"(?i)(?:^|[^-\w])(?P<function>(?:-webkit-)?image-set\()"#);
+
+// `2x`, `2dppx`, `192dpi`, `75.6dpcm`
+regex!(static RE_RESOLUTION, r#"(?i)^(?P<value>\d*\.?\d+)(?P<unit>x|dppx|dpi|dpcm)$"#);
+
 const EMPTY: &str = "";
 
-pub const EXTERN: SH = ("link[rel=stylesheet]", external);
-pub const INTERN: SH = ("style", internal);
-pub const INLINE: SH = ("[style]", inline);
+// absolute URLs `rebase()` made of relative ones, to what they were
+type Rebased = HashMap<String, String>;
+
+pub const EXTERN: SH = ("css::external", "link[rel=stylesheet]", external);
+pub const INTERN: SH = ("css::internal", "style", internal);
+pub const INLINE: SH = ("css::inline", "[style]", inline);
 
 fn external(node: &NodeDataRef<ElementData>) {
-    retry!(node.attributes.try_borrow())
-        .map(|attr| attr.get("href")
-                        .map(utils::load_string)
-                        .transpose()
-                        .ok())
-        .expect("cannot find `href` attr in <link />")
-        .flatten()
-        .map(|css| patch(node.as_node(), css));
+    let href = match utils::attributes(node).and_then(|attr| attr.get("href").map(ToOwned::to_owned)) {
+        Some(href) => href,
+        None => return,
+    };
+
+    if let Ok((url, css)) = utils::load_string_and_url(&href) {
+        let (css, rebased) = rebase(&css, &url);
+        patch(node.as_node(), css, rebased);
+    }
 }
 
 fn internal(node: &NodeDataRef<ElementData>) {
-    patch(node.as_node(), node.text_contents())
+    patch(node.as_node(), node.text_contents(), Rebased::new())
 }
 
 fn inline(node: &NodeDataRef<ElementData>) {
-    retry!(node.attributes.try_borrow_mut())
-        .map(|mut a| a.get_mut("style")
-                      .map(patch_url))
-        .expect("cannot find `href` attr in <link />");
+    if let Some(style) = utils::attributes_mut(node).as_mut().and_then(|a| a.get_mut("style")) {
+        *style = image_sets(style, OPT.srcset);
+        patch_url(style, &Rebased::new());
+    }
+}
+
+/// Every `@import` and `url()` location in a stylesheet
+pub fn references(css: &str) -> Vec<&str> {
+    let mut seen = HashSet::new();
+
+    RE_IMPORT.captures_iter(css)
+             .chain(RE_URL.captures_iter(css))
+             .filter_map(|cap| cap.name("url"))
+             .map(|m| m.as_str().trim())
+             .filter(|url| seen.insert(*url))
+             .collect()
+}
+
+/// Makes relative `@import`s and `url()`s absolute: they are relative
+/// to the stylesheet, not to the page it ends up in. What is not inlined
+/// in the end gets its relative reference back, see `Rebased`
+fn rebase(css: &str, base: &Url) -> (String, Rebased) {
+    let rebased = RefCell::new(Rebased::new());
+
+    let absolute = |re: &Regex, css: &str| {
+        re.replace_all(css, |cap: &Captures| {
+            let all = cap.get(0).expect("there is always a match");
+            let url = cap.name("url").expect("`url` group is not optional");
+
+            let href = url.as_str().trim();
+
+            // fragments point into the document using the stylesheet (SVG filters and such)
+            if href.starts_with('#') || Url::parse(href).is_ok() {
+                return all.as_str().to_owned();
+            }
+
+            match base.join(href) {
+                Ok(abs) => {
+                    rebased.borrow_mut().insert(abs.to_string(), href.to_owned());
+
+                    format!("{}{}{}",
+                            &all.as_str()[..url.start() - all.start()],
+                            abs,
+                            &all.as_str()[url.end() - all.start()..])
+                }
+                Err(_) => all.as_str().to_owned(),
+            }
+        })
+        .into_owned()
+    };
+
+    // bare strings of `image-set()` are URLs too
+    let css = image_sets(css, Policy::All);
+    let css = absolute(&RE_URL, &absolute(&RE_IMPORT, &css));
+
+    (css, rebased.into_inner())
 }
 
-fn patch(node: &NodeRef, mut content: String) {
+/// Turns `image-set()` candidates into `url()`s for `patch_url()` to find, or into
+/// a single `url()` of the one the --srcset policy picks
+fn image_sets(css: &str, policy: Policy) -> String {
+    let mut patched = String::with_capacity(css.len());
+    let mut rest = css;
+
+    while let Some(m) = RE_IMAGE_SET.captures(rest).and_then(|c| c.name("function")) {
+        patched.push_str(&rest[..m.start()]);
+
+        let args = &rest[m.end()..];
+
+        let close = match top_level(args).last() {
+            Some(&(i, ')')) => i,
+            // unbalanced, better leave it alone
+            _ => {
+                patched.push_str(&rest[m.start()..]);
+                return patched;
+            }
+        };
+
+        patched.push_str(&image_set(m.as_str(), &args[..close], policy));
+        rest = &args[close + 1..];
+    }
+
+    patched.push_str(rest);
+    patched
+}
+
+fn image_set(function: &str, args: &str, policy: Policy) -> String {
+    let mut start = 0;
+    let mut parts = vec![];
+
+    for (i, _) in top_level(args) {
+        parts.push(args[start..i].trim());
+        start = i + 1;
+    }
+
+    parts.push(args[start..].trim());
+
+    // `(argument, image URL, what follows it)`, gradients have no URL
+    let parts = parts.into_iter()
+                     .filter(|p| !p.is_empty())
+                     .map(|p| match image(p) {
+                         Some((url, tail)) => (p, Some(url), tail),
+                         None => (p, None, ""),
+                     })
+                     .collect::<Vec<_>>();
+
+    let candidates = parts.iter()
+                          .filter_map(|(_, url, tail)| {
+                              let density = tail.split_ascii_whitespace()
+                                                .find_map(resolution)
+                                                .unwrap_or(1.0);
+
+                              Some((Candidate { url: (*url)?.to_owned(), descriptor: format!("{}x", density) }, srcset::slot(None)))
+                          })
+                          .collect::<Vec<_>>();
+
+    if let Some(chosen) = srcset::pick(&candidates, policy) {
+        log!(debug, "{} is {}", function, chosen.url);
+        return format!("url(\"{}\")", chosen.url);
+    }
+
+    let parts = parts.iter()
+                     .map(|(part, url, tail)| match url {
+                         Some(url) if !part.to_ascii_lowercase().starts_with("url(") => format!("url(\"{}\"){}", url, tail),
+                         _ => part.to_string(),
+                     })
+                     .collect::<Vec<_>>();
+
+    format!("{}{})", function, parts.join(", "))
+}
+
+// `"a.png" 2x` or `url(a.png) 2x type("image/png")` into the URL and the rest
+fn image(part: &str) -> Option<(&str, &str)> {
+    if let Some(quote) = part.chars().next().filter(|c| *c == '"' || *c == '\'') {
+        let end = part[1..].find(quote)? + 1;
+        return Some((&part[1..end], &part[end + 1..]));
+    }
+
+    if part.len() > 4 && part[..4].eq_ignore_ascii_case("url(") {
+        let end = top_level(&part[4..]).into_iter().find(|(_, c)| *c == ')')?.0 + 4;
+        return Some((part[4..end].trim().trim_matches(['"', '\'']), &part[end + 1..]));
+    }
+
+    None
+}
+
+// density as in `x`
+fn resolution(descriptor: &str) -> Option<f64> {
+    let cap = RE_RESOLUTION.captures(descriptor)?;
+    let value = cap["value"].parse::<f64>().ok()?;
+
+    match cap["unit"].to_ascii_lowercase().as_str() {
+        "dpi" => Some(value / 96.0),
+        "dpcm" => Some(value * 2.54 / 96.0),
+        _ => Some(value),
+    }
+}
+
+// `,` and the closing `)` that are neither nested nor quoted
+fn top_level(s: &str) -> Vec<(usize, char)> {
+    let mut depth = 0usize;
+    let mut quote = None;
+    let mut found = vec![];
+
+    for (i, c) in s.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') if depth == 0 => {
+                found.push((i, c));
+                break;
+            }
+            (None, ')') => depth -= 1,
+            (None, ',') if depth == 0 => found.push((i, c)),
+            _ => {}
+        }
+    }
+
+    found
+}
+
+fn patch(node: &NodeRef, mut content: String, mut rebased: Rebased) {
     use html5ever::{interface::QualName, local_name, namespace_url, ns};
 
     log!(info, "looking for @import's");
-    patch_import(&mut content);
+    patch_import(&mut content, &mut rebased);
+
+    content = image_sets(&content, OPT.srcset);
 
     log!(info, "looking for url()'s");
-    patch_url(&mut content);
+    patch_url(&mut content, &rebased);
 
     let elm = NodeRef::new_element(
         QualName::new(None, ns!(html), local_name!("style")),
@@ -83,24 +276,34 @@ fn patch(node: &NodeRef, mut content: String) {
     node.detach();
 }
 
-fn patch_import(css: &mut String) {
+// A rebased `@import` that cannot be loaded is looked for next to the page,
+// where pages made for older versions have it
+fn patch_import(css: &mut String, rebased: &mut Rebased) {
     let map = RwLock::new(HashMap::new());
+    let more = RwLock::new(Rebased::new());
 
     RE_IMPORT
-        .captures_iter(&css)
+        .captures_iter(css)
         .for_each(|v| {
             log::info!("patch_import() {:?}", v.name("url"));
         });
 
+    let initiator = utils::initiator();
+
     // Deduplicate @import URLs, download in parallel and make lookup table "url => content"
     RE_IMPORT
-        .captures_iter(&css)
+        .captures_iter(css)
         .filter_map(|cap| cap.name("url").map(Into::into))
         .collect::<HashSet<&str>>()
         .par_iter()
         .for_each(|&url| {
             log!(debug, "patch_import() downloading {}", url);
-            if let Ok(content) = utils::load_string(url) {
+            let fallback = rebased.get(url).map(String::as_str);
+
+            if let Ok((base, content)) = utils::with_initiator(initiator.clone(), || utils::load_string_and_url_or(url, fallback)) {
+                let (content, urls) = rebase(&content, &base);
+                more.write().expect("cannot reach rebased URLs").extend(urls);
+
                 let mut map = map.write().expect("cannot reach shared HashMap out");
                 map.insert(url, content);
             }
@@ -110,7 +313,7 @@ fn patch_import(css: &mut String) {
     log!(trace, "patch_import()\n{:#?}", urls);
 
     let patched = RE_IMPORT
-        .replace_all(&css, |cap: &Captures| {
+        .replace_all(css, |cap: &Captures| {
 
             urls.get(&cap["url"])
                 .map(|content| {
@@ -127,26 +330,44 @@ fn patch_import(css: &mut String) {
                 })
                 .unwrap_or_else(|| {
                     log!(debug, "leaving @import as is {}", &cap[0]);
-                    cap[0].to_owned()
+
+                    match rebased.get(&cap["url"]) {
+                        Some(href) => cap[0].replacen(&cap["url"], href, 1),
+                        None => cap[0].to_owned(),
+                    }
                 })
     });
 
     log!(trace, "patch_import()\n{}", patched);
     *css = patched.into_owned();
+
+    rebased.extend(more.into_inner().expect("cannot unwrap RwLock"));
 }
 
-fn patch_url(css: &mut String) {
+// References `rebase()` made absolute fall back to the page like `patch_import()`'s,
+// and go back to how they were written if they can't be inlined
+fn patch_url(css: &mut String, rebased: &Rebased) {
     let map = RwLock::new(HashMap::new());
 
+    // `url()`s are images to filter lists, `css::inline::url` and the like
+    let initiator = utils::initiator().map(|i| utils::Initiator { handler: format!("{}::url", i.handler), ..i });
+
     // Deduplicate URLs, download in parallel and make lookup table "url => data_uri"
     RE_URL
-        .captures_iter(&css)
+        .captures_iter(css)
         .filter_map(|cap| cap.name("url").map(Into::into))
         .collect::<HashSet<&str>>()
         .par_iter()
         .for_each(|&url| {
+            let fallback = rebased.get(url.trim()).map(String::as_str);
+
             let mut new_url = url.to_owned();
-            utils::make_data_uri(&mut new_url);
+            utils::with_initiator(initiator.clone(), || utils::make_data_uri_or(&mut new_url, fallback));
+
+            if let (false, Some(href)) = (new_url.starts_with("data:"), fallback) {
+                new_url = href.to_owned();
+            }
+
             let mut map = map.write().expect("cannot reach shared HashMap");
             map.insert(url, format!("url({})", new_url));
         });
@@ -155,13 +376,12 @@ fn patch_url(css: &mut String) {
     log!(trace, "patch_url()\n{:#?}", urls);
 
     let patched = RE_URL
-        .replace_all(&css, |cap: &Captures| {
+        .replace_all(css, |cap: &Captures| {
 
             urls.get(&cap["url"])
                 .map(ToOwned::to_owned)
-                .map(|v| {
+                .inspect(|_| {
                     log!(debug, "making datauri for {}", &cap[0]);
-                    v
                 })
                 .unwrap_or_else(|| {
                     log!(debug, "skipping {}", &cap[0]);
@@ -228,7 +448,7 @@ mod tests {
         ];
 
         RE_IMPORT
-            .captures_iter(&CSS)
+            .captures_iter(CSS)
             .enumerate()
             .for_each(|(i, c)| {
                 let url = c.name("url").map_or("", |m| m.as_str());
@@ -256,10 +476,62 @@ mod tests {
         ];
 
         RE_URL
-            .captures_iter(&CSS)
+            .captures_iter(CSS)
             .enumerate()
             .for_each(|(i, c)| {
                 assert_eq!(expected[i], (c.get(1).map_or("", |m| m.as_str())));
             })
     }
+
+    #[test]
+    fn rebase_urls() {
+        let base = Url::parse("https://cdn.example.com/css/site.css").unwrap();
+
+        let (css, _) = rebase(r#"
+            @import 'print.css' print;
+            @import url("chrome://communicator/skin/");
+            .a { background: url( ../img/a.png ) }
+            .b { background: url("//other.example.com/b.png") }
+            .c { background: url(data:image/gif;base64,R0lGODdh) }
+            .d { filter: url(#blur) }
+        "#, &base);
+
+        assert_eq!(references(&css), [
+            "https://cdn.example.com/css/print.css",
+            "chrome://communicator/skin/",
+            "https://cdn.example.com/img/a.png",
+            "https://other.example.com/b.png",
+            "data:image/gif;base64,R0lGODdh",
+            "#blur",
+        ]);
+    }
+
+    #[test]
+    fn image_set_candidates() {
+        let css = r#".a { background-image: -webkit-image-set("a.png" 1x, url('a@2x.png') 2x); }
+                     .b { background: image-set("b.avif" type("image/avif") 192dpi, linear-gradient(red, blue) 1x) }"#;
+
+        assert_eq!(image_sets(css, Policy::All),
+                   r#".a { background-image: -webkit-image-set(url("a.png") 1x, url('a@2x.png') 2x); }
+                     .b { background: image-set(url("b.avif") type("image/avif") 192dpi, linear-gradient(red, blue) 1x) }"#);
+
+        assert_eq!(image_sets(css, Policy::Smallest),
+                   r#".a { background-image: url("a.png"); }
+                     .b { background: url("b.avif") }"#);
+
+        assert_eq!(image_sets(css, Policy::Dpr(1.5)),
+                   r#".a { background-image: url("a@2x.png"); }
+                     .b { background: url("b.avif") }"#);
+
+        // strings get rebased like any url()
+        let base = Url::parse("https://cdn.example.com/css/site.css").unwrap();
+        assert_eq!(references(&rebase(r#".c { background: image-set("../img/c.png" 1x, "c@2x.png" 2x) }"#, &base).0),
+                   ["https://cdn.example.com/img/c.png", "https://cdn.example.com/css/c@2x.png"]);
+
+        assert_eq!(image_sets("image-set(\"a.png\" 1x", Policy::All), "image-set(\"a.png\" 1x");
+
+        let prefixed = r#".d { background: -moz-image-set("d.png" 1x); mask: x-image-set("e.png" 1x), image-set("f.png" 1x) }"#;
+        assert_eq!(image_sets(prefixed, Policy::All),
+                   r#".d { background: -moz-image-set("d.png" 1x); mask: x-image-set("e.png" 1x), image-set(url("f.png") 1x) }"#);
+    }
 }
@@ -3,12 +3,11 @@ use crate::utils;
 
 use kuchiki::{ElementData, NodeDataRef};
 
-pub const TAG: SH = (r#"link[rel="shortcut icon"], link[rel="icon"], link[rel="apple-touch-icon"]"#, favicon);
+pub const TAG: SH = ("favicon", r#"link[rel="shortcut icon"], link[rel="icon"], link[rel="apple-touch-icon"]"#, favicon);
 
 // oh my... https://en.wikipedia.org/wiki/Favicon
 fn favicon(node: &NodeDataRef<ElementData>) {
-    retry!(node.attributes.try_borrow_mut())
-        .map(|mut attr| attr.get_mut("href")
-                            .map(utils::make_data_uri))
-        .expect("cannot inline favicon");
+    if let Some(href) = utils::attributes_mut(node).as_mut().and_then(|attr| attr.get_mut("href")) {
+        utils::make_data_uri(href);
+    }
 }
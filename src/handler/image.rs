@@ -1,14 +1,32 @@
 use crate::SH;
+use crate::srcset;
 use crate::utils;
 
 use kuchiki::{ElementData, NodeDataRef};
+use rayon::prelude::*;
 
-pub const TAG: SH = ("img", image);
+pub const TAG: SH = ("image", "img", image);
+pub const SRCSET_TAG: SH = ("image::srcset", "img[srcset], picture > source[srcset]", srcset);
 
 fn image(node: &NodeDataRef<ElementData>) {
-    retry!(node.attributes.try_borrow_mut())
-        .map(|mut attr| attr.get_mut("src")
-                            .map(utils::make_data_uri))
-        .expect("cannot inline <img />");
+    if let Some(src) = utils::attributes_mut(node).as_mut().and_then(|attr| attr.get_mut("src")) {
+        utils::make_data_uri(src);
+    }
 }
 
+// what is left after srcset::collapse(), every candidate
+fn srcset(node: &NodeDataRef<ElementData>) {
+    let mut candidates = match utils::attributes(node).and_then(|attr| attr.get("srcset").map(srcset::parse)) {
+        Some(candidates) => candidates,
+        None => return,
+    };
+
+    let initiator = utils::initiator();
+
+    candidates.par_iter_mut()
+              .for_each(|c| utils::with_initiator(initiator.clone(), || utils::make_data_uri(&mut c.url)));
+
+    if let Some(mut attr) = utils::attributes_mut(node) {
+        attr.insert("srcset", srcset::serialize(&candidates));
+    }
+}
@@ -3,9 +3,9 @@ use crate::utils;
 
 use kuchiki::{ElementData, NodeDataRef, NodeRef};
 
-pub const SCRIPT_TAG: SH = ("script[src]", external);
-pub const LINK_TAG: SH = ("link[type='application/x-javascript'], link[type='application/javascript'], link[type='text/javascript']", external);
-pub const LINK_JSON_TAG: SH = ("link[type='application/json']", external);
+pub const SCRIPT_TAG: SH = ("script", "script[src]", external);
+pub const LINK_TAG: SH = ("script::link", "link[type='application/x-javascript'], link[type='application/javascript'], link[type='text/javascript']", external);
+pub const LINK_JSON_TAG: SH = ("script::json", "link[type='application/json']", external);
 
 fn external(node: &NodeDataRef<ElementData>) {
     let tag = node.name.local.to_string();
@@ -15,14 +15,14 @@ fn external(node: &NodeDataRef<ElementData>) {
         _ => return,
     };
 
-    retry!(node.attributes.try_borrow())
-        .map(|a| a.get(attr)
-                  .map(utils::load_string)
-                  .transpose()
-                  .ok())
-        .expect(&format!("cannot find `{}` attr in <{} />", attr, tag))
-        .flatten()
-        .map(|script| replace(node.as_node(), script));
+    let src = match utils::attributes(node).and_then(|a| a.get(attr).map(ToOwned::to_owned)) {
+        Some(src) => src,
+        None => return,
+    };
+
+    if let Ok(script) = utils::load_string(&src) {
+        replace(node.as_node(), script);
+    }
 }
 
 fn replace(node: &NodeRef, content: String) {
@@ -0,0 +1,271 @@
+use crate::OPT;
+use crate::utils::{self, Response};
+
+use anyhow::Result;
+use once_cell::sync::Lazy;
+use serde_json::{Value, json};
+use url::Url;
+
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+const PAGE_ID: &str = "page_1";
+
+// HAR files get shared, secrets should not
+const REDACTED: &[&str] = &["authorization", "proxy-authorization", "cookie", "set-cookie"];
+
+static ENTRIES: Lazy<Mutex<Vec<Value>>> = Lazy::new(|| Mutex::new(Vec::new()));
+static STARTED: Lazy<SystemTime> = Lazy::new(SystemTime::now);
+
+/// Remembers a `load_url()` call if `--har` is given, one entry per redirect
+pub fn record(url: &Url, started: SystemTime, time: Duration, resp: &Result<Response>) {
+    if OPT.har.is_none() {
+        return;
+    }
+
+    Lazy::force(&STARTED);
+
+    let entries = entries(url, started, time, resp);
+
+    ENTRIES.lock()
+           .expect("cannot reach HAR entries")
+           .extend(entries);
+}
+
+// redirects take the time they took, the final response the rest
+fn entries(url: &Url, started: SystemTime, time: Duration, resp: &Result<Response>) -> Vec<Value> {
+    let resp = match resp {
+        Ok(resp) => resp,
+        Err(e) => return vec![entry(url, started, time, Err(e))],
+    };
+
+    let mut entries = vec![];
+    let mut at = started;
+    let mut left = time;
+
+    for hop in &resp.redirects {
+        entries.push(entry(&hop.url, at, hop.time, Ok(hop)));
+        at += hop.time;
+        left = left.saturating_sub(hop.time);
+    }
+
+    entries.push(entry(&resp.url, at, left, Ok(resp)));
+    entries
+}
+
+fn entry(url: &Url, started: SystemTime, time: Duration, resp: Result<&Response, &anyhow::Error>) -> Value {
+    let time = time.as_secs_f64() * 1000.0;
+
+    let request_headers = resp.as_ref()
+                              .map_or_else(|_| vec![], |r| headers(&r.request_headers));
+
+    let response = match resp {
+        Ok(resp) => json!({
+            "status": resp.status,
+            "statusText": resp.reason,
+            "httpVersion": http_version(url),
+            "headers": headers(&resp.headers),
+            "cookies": [],
+            "content": {
+                "size": resp.data.len(),
+                "mimeType": resp.mime,
+            },
+            "redirectURL": resp.headers
+                               .iter()
+                               .find(|(k, _)| k.eq_ignore_ascii_case("location"))
+                               .map_or("", |(_, v)| v.as_str()),
+            "headersSize": -1,
+            "bodySize": resp.data.len(),
+        }),
+        Err(_) => json!({
+            "status": 0,
+            "statusText": "",
+            "httpVersion": "",
+            "headers": [],
+            "cookies": [],
+            "content": {
+                "size": 0,
+                "mimeType": "x-unknown",
+            },
+            "redirectURL": "",
+            "headersSize": -1,
+            "bodySize": -1,
+        }),
+    };
+
+    let initiator = match utils::initiator() {
+        Some(i) => json!({
+            "type": "parser",
+            "handler": i.handler,
+            "node": i.node,
+        }),
+        None => json!({ "type": "other" }),
+    };
+
+    let mut entry = json!({
+        "pageref": PAGE_ID,
+        "startedDateTime": timestamp(started),
+        "time": time,
+        "request": {
+            "method": "GET",
+            "url": url.as_str(),
+            "httpVersion": http_version(url),
+            "headers": request_headers,
+            "queryString": url.query_pairs()
+                              .map(|(k, v)| json!({ "name": k, "value": v }))
+                              .collect::<Vec<_>>(),
+            "cookies": [],
+            "headersSize": -1,
+            "bodySize": 0,
+        },
+        "response": response,
+        "cache": {},
+        "timings": {
+            "send": 0,
+            "wait": time,
+            "receive": 0,
+        },
+        "_initiator": initiator,
+    });
+
+    if let Err(e) = resp {
+        entry["_error"] = json!(e.to_string());
+    }
+
+    entry
+}
+
+pub fn save(path: &Path) -> Result<()> {
+    let mut entries = std::mem::take(&mut *ENTRIES.lock().expect("cannot reach HAR entries"));
+
+    // entries are pushed once finished, viewers expect them by start time
+    entries.sort_by(|a, b| a["startedDateTime"].as_str().cmp(&b["startedDateTime"].as_str()));
+
+    let title = OPT.get_page()
+                   .map_or_else(|| "stdin".to_owned(), String::from);
+
+    let har = json!({
+        "log": {
+            "version": "1.2",
+            "creator": {
+                "name": env!("CARGO_PKG_NAME"),
+                "version": env!("CARGO_PKG_VERSION"),
+            },
+            "pages": [{
+                "startedDateTime": timestamp(*STARTED),
+                "id": PAGE_ID,
+                "title": title,
+                "pageTimings": {},
+            }],
+            "entries": entries,
+        }
+    });
+
+    log!(info, "writing HAR log to {}", path.display());
+
+    fs::write(path, serde_json::to_vec_pretty(&har)?)?;
+
+    Ok(())
+}
+
+fn headers(headers: &[(String, String)]) -> Vec<Value> {
+    headers.iter()
+           .map(|(k, v)| match REDACTED.contains(&k.to_lowercase().as_str()) {
+               true => json!({ "name": k, "value": "<redacted>" }),
+               false => json!({ "name": k, "value": v }),
+           })
+           .collect()
+}
+
+fn http_version(url: &Url) -> &'static str {
+    match url.scheme() {
+        "http" | "https" => "HTTP/1.1",
+        _ => "",
+    }
+}
+
+fn timestamp(time: SystemTime) -> String {
+    humantime::format_rfc3339_millis(time).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failed_entry() {
+        let url = Url::parse("https://example.com/i.png?v=1").unwrap();
+        let initiator = utils::Initiator {
+            handler: "image".to_owned(),
+            element: "img".to_owned(),
+            attributes: vec![("src".to_owned(), "i.png?v=1".to_owned())],
+            node: "<img src=\"i.png?v=1\" />".to_owned(),
+        };
+
+        let entry = utils::with_initiator(Some(initiator), || {
+            entry(&url, SystemTime::now(), Duration::from_millis(5), Err(&anyhow::anyhow!("boom")))
+        });
+
+        assert_eq!(entry["response"]["status"], 0);
+        assert_eq!(entry["request"]["queryString"][0]["value"], "1");
+        assert_eq!(entry["_initiator"]["handler"], "image");
+        assert_eq!(entry["_error"], "boom");
+    }
+
+    #[test]
+    fn redirect_entries() {
+        let response = |url: &str, status: i32, headers: &[(&str, &str)], time: u64| Response {
+            url: Url::parse(url).unwrap(),
+            status,
+            reason: String::new(),
+            request_headers: vec![],
+            headers: headers.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            mime: String::new(),
+            data: vec![],
+            time: Duration::from_millis(time),
+            redirects: vec![],
+        };
+
+        let mut resp = response("https://www.example.com/", 200, &[], 30);
+        resp.redirects.push(response("http://example.com/", 301, &[("Location", "https://www.example.com/")], 20));
+
+        let url = Url::parse("http://example.com/").unwrap();
+        let entries = entries(&url, SystemTime::now(), Duration::from_millis(100), &Ok(resp));
+
+        let summary = entries.iter()
+                             .map(|e| format!("{} {} {} {}", e["request"]["url"], e["response"]["status"],
+                                              e["response"]["redirectURL"], e["time"]))
+                             .collect::<Vec<_>>();
+
+        assert_eq!(summary, [
+            r#""http://example.com/" 301 "https://www.example.com/" 20.0"#,
+            r#""https://www.example.com/" 200 "" 80.0"#,
+        ]);
+    }
+
+    #[test]
+    fn redacted_headers() {
+        let pairs = [
+            ("Accept", "*/*"),
+            ("Authorization", "Basic YWxpY2U6czNjcmV0"),
+            ("proxy-authorization", "Basic Ym9iOmh1bnRlcjI="),
+            ("Cookie", "sid=42"),
+            ("Set-Cookie", "sid=43; HttpOnly"),
+        ];
+
+        let values = headers(&pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect::<Vec<_>>())
+            .iter()
+            .map(|h| format!("{}: {}", h["name"].as_str().unwrap(), h["value"].as_str().unwrap()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(values, [
+            "Accept: */*",
+            "Authorization: <redacted>",
+            "proxy-authorization: <redacted>",
+            "Cookie: <redacted>",
+            "Set-Cookie: <redacted>",
+        ]);
+    }
+}
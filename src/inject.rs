@@ -0,0 +1,122 @@
+use anyhow::{Result, anyhow};
+use html5ever::{interface::QualName, namespace_url, ns};
+use kuchiki::{Attribute, ExpandedName, NodeRef};
+use url::Url;
+
+use std::path::Path;
+use std::time::SystemTime;
+
+/// Adds `<link rel=stylesheet>` and `<script src>` for files or URLs given by the user
+/// and returns their URLs; handlers inline them like any other
+pub fn inject(html: &NodeRef, cwd: &Path, css: &[String], js: &[String]) -> Result<Vec<Url>> {
+    let mut injected = vec![];
+
+    if let Ok(head) = html.select_first("head") {
+        for href in css {
+            let url = to_url(cwd, href)?;
+            head.as_node().append(element("link", &[("rel", "stylesheet"), ("href", url.as_str())]));
+            injected.push(url);
+        }
+    }
+
+    if let Ok(body) = html.select_first("body") {
+        for src in js {
+            let url = to_url(cwd, src)?;
+            body.as_node().append(element("script", &[("src", url.as_str())]));
+            injected.push(url);
+        }
+    }
+
+    Ok(injected)
+}
+
+/// Where and when the page was captured, as `<meta>` tags and a comment on top
+pub fn stamp(html: &NodeRef, source: Option<&Url>, captured: SystemTime, inlined: usize) {
+    let captured = humantime::format_rfc3339_seconds(captured).to_string();
+    let generator = format!("{} {}", env!("CARGO_BIN_NAME"), env!("CARGO_PKG_VERSION"));
+
+    if let Ok(head) = html.select_first("head") {
+        let source = source.map(Url::as_str);
+        let inlined = inlined.to_string();
+
+        let metas = [
+            ("archive:source", source),
+            ("archive:captured", Some(captured.as_str())),
+            ("archive:generator", Some(generator.as_str())),
+            ("archive:resources", Some(inlined.as_str())),
+        ];
+
+        for (name, content) in metas.iter().filter_map(|(n, c)| Some((n, (*c)?))) {
+            head.as_node().append(element("meta", &[("name", name), ("content", content)]));
+        }
+    }
+
+    // `--` can't be in a comment
+    let comment = NodeRef::new_comment(format!(
+        " Saved from {} on {} by {}, {} resource(s) inlined ",
+        source.map_or("standard input", Url::as_str).replace("--", "%2D%2D"),
+        captured,
+        generator,
+        inlined,
+    ));
+
+    // after the doctype, anything before it puts old browsers in quirks mode
+    match html.children().find(|n| n.as_doctype().is_some()) {
+        Some(doctype) => doctype.insert_after(comment),
+        None => html.prepend(comment),
+    }
+}
+
+// anything that is not a URL is a local path
+fn to_url(cwd: &Path, s: &str) -> Result<Url> {
+    match Url::parse(s) {
+        Ok(url) if url.scheme().len() > 1 => Ok(url),
+        _ => Url::from_file_path(cwd.join(s)).map_err(|_| anyhow!("cannot turn `{}` into a file URL", s)),
+    }
+}
+
+fn element(name: &str, attributes: &[(&str, &str)]) -> NodeRef {
+    NodeRef::new_element(
+        QualName::new(None, ns!(html), name.into()),
+        attributes.iter().map(|(k, v)| (
+            ExpandedName::new("", *k),
+            Attribute {
+                prefix: None,
+                value: v.to_string(),
+            },
+        )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use kuchiki::traits::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn inject_and_stamp() {
+        let html = kuchiki::parse_html().one("<!DOCTYPE html><title>t</title><p>text</p>");
+
+        inject(&html, Path::new("/srv"), &["banner.css".to_owned()], &["https://example.com/a.js".to_owned()]).unwrap();
+
+        let source = Url::parse("https://example.com/page--1").unwrap();
+        stamp(&html, Some(&source), UNIX_EPOCH + Duration::from_secs(1_700_000_000), 3);
+
+        let version = env!("CARGO_PKG_VERSION");
+
+        assert_eq!(html.to_string(), format!(concat!(
+            "<!DOCTYPE html>",
+            "<!-- Saved from https://example.com/page%2D%2D1 on 2023-11-14T22:13:20Z by inline {v}, 3 resource(s) inlined -->",
+            "<html><head><title>t</title>",
+            r#"<link href="file:///srv/banner.css" rel="stylesheet">"#,
+            r#"<meta content="https://example.com/page--1" name="archive:source">"#,
+            r#"<meta content="2023-11-14T22:13:20Z" name="archive:captured">"#,
+            r#"<meta content="inline {v}" name="archive:generator">"#,
+            r#"<meta content="3" name="archive:resources">"#,
+            "</head><body><p>text</p>",
+            r#"<script src="https://example.com/a.js"></script>"#,
+            "</body></html>",
+        ), v = version));
+    }
+}
@@ -0,0 +1,146 @@
+use anyhow::{Result, bail};
+use kuchiki::NodeRef;
+
+use std::str::FromStr;
+
+/// Conventions of the common lazy-loading scripts
+pub const DEFAULT: &str = "data-src=src,data-lazy-src=src,data-original=src,data-srcset=srcset,data-lazy-srcset=srcset,data-poster=poster,data-bg=style,data-background-image=style";
+
+/// `ATTR=TARGET` as given to --lazy-attrs, `ATTR` holds what belongs in `TARGET`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    pub attr: String,
+    pub target: Target,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Target {
+    Src,
+    Srcset,
+    Poster,
+    // a `background-image` in `style`
+    Style,
+}
+
+impl FromStr for Rule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (attr, target) = match s.split_once('=') {
+            Some((attr, target)) if !attr.trim().is_empty() => (attr.trim(), target.trim()),
+            _ => bail!("expected `ATTR=TARGET`, got `{}`", s),
+        };
+
+        let target = match target {
+            "src" => Target::Src,
+            "srcset" => Target::Srcset,
+            "poster" => Target::Poster,
+            "style" => Target::Style,
+            _ => bail!("unknown target `{}` (use src, srcset, poster or style)", target),
+        };
+
+        Ok(Rule {
+            attr: attr.to_lowercase(),
+            target,
+        })
+    }
+}
+
+/// Moves lazy-loaded URLs to where handlers look for them
+pub fn promote(html: &NodeRef, rules: &[Rule]) {
+    for rule in rules {
+        let found = html.select(&format!("[{}]", rule.attr))
+                        .map_or(vec![], |v| v.collect());
+
+        for elm in found {
+            let mut attrs = elm.attributes.borrow_mut();
+
+            let value = match attrs.remove(rule.attr.as_str()) {
+                Some(attr) if !attr.value.trim().is_empty() => attr.value.trim().to_owned(),
+                _ => continue,
+            };
+
+            log!(debug, "promoting {}=\"{}\" of <{}>", rule.attr, value, elm.name.local);
+
+            match rule.target {
+                Target::Src => { attrs.insert("src", value); }
+                Target::Srcset => { attrs.insert("srcset", value); }
+                Target::Poster => { attrs.insert("poster", value); }
+                Target::Style => {
+                    let style = attrs.get("style").unwrap_or_default().trim().trim_end_matches(';').to_owned();
+                    let background = match is_image_function(&value) {
+                        true => format!("background-image: {}", value),
+                        false => format!("background-image: url(\"{}\")", value.replace('"', "%22")),
+                    };
+
+                    attrs.insert("style", match style.is_empty() {
+                        true => background,
+                        false => format!("{}; {}", style, background),
+                    });
+                }
+            }
+        }
+    }
+}
+
+// `url(...)`, `image-set(...)` or a gradient is already a CSS image, not a URL to wrap
+fn is_image_function(value: &str) -> bool {
+    let name = match value.split_once('(') {
+        Some((name, _)) => name.trim().to_ascii_lowercase(),
+        None => return false,
+    };
+
+    name == "url" || name.ends_with("image-set") || name.ends_with("gradient")
+}
+
+/// `loading="lazy"` means nothing for an image that is part of the page
+pub fn strip_loading(html: &NodeRef) {
+    let found = html.select(r#"img[loading="lazy"], iframe[loading="lazy"]"#)
+                    .map_or(vec![], |v| v.collect());
+
+    for elm in found {
+        let mut attrs = elm.attributes.borrow_mut();
+
+        if attrs.get("src").is_some_and(|src| src.starts_with("data:")) {
+            attrs.remove("loading");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use kuchiki::traits::*;
+
+    #[test]
+    fn promote_attributes() {
+        let rules = DEFAULT.split(',')
+                           .map(|r| r.parse::<Rule>().unwrap())
+                           .collect::<Vec<_>>();
+
+        let html = kuchiki::parse_html().one(r#"
+            <img src="placeholder.gif" data-src="real.jpg" data-srcset="real.jpg 1x, real@2x.jpg 2x" loading="lazy">
+            <img src="kept.png" data-src=" ">
+            <div data-bg="hero.jpg" style="color: red;"></div>
+            <div data-bg="url('a.jpg'), linear-gradient(red, blue)"></div>
+            <div data-bg="-webkit-image-set(url(b.jpg) 1x)"></div>
+            <div data-bg="a(1).jpg"></div>
+        "#);
+
+        promote(&html, &rules);
+
+        let body = html.select_first("body").unwrap().as_node().to_string();
+        let body = body.split_whitespace().collect::<Vec<_>>().join(" ");
+
+        assert_eq!(body, r#"<body><img loading="lazy" src="real.jpg" srcset="real.jpg 1x, real@2x.jpg 2x"> <img src="kept.png"> <div style="color: red; background-image: url(&quot;hero.jpg&quot;)"></div> <div style="background-image: url('a.jpg'), linear-gradient(red, blue)"></div> <div style="background-image: -webkit-image-set(url(b.jpg) 1x)"></div> <div style="background-image: url(&quot;a(1).jpg&quot;)"></div> </body>"#);
+
+        html.select_first("img").unwrap().attributes.borrow_mut().insert("src", "data:,".to_owned());
+        strip_loading(&html);
+
+        assert!(html.select_first("img[loading]").is_err());
+
+        assert!("data-src=href".parse::<Rule>().is_err());
+        assert!("=src".parse::<Rule>().is_err());
+    }
+}
@@ -1,12 +1,32 @@
 #[macro_use]
 mod utils;
+mod adblock;
+mod archive;
+mod audit;
+mod charset;
+mod check;
+mod compress;
+mod filter;
+mod har;
 mod handler;
+mod http;
+mod inject;
+mod lazy;
+mod noscript;
+mod report;
+mod prune;
+mod reader;
+mod rewrite;
+mod sanitize;
+mod srcset;
+mod throttle;
+mod tls;
 
 use anyhow::{Error, Result, bail};
 use atty::{Stream::Stdin};
 use human_panic::setup_panic;
 use kuchiki::{NodeRef, NodeDataRef, ElementData, traits::*};
-use once_cell::sync::Lazy;
+use once_cell::sync::{Lazy, OnceCell};
 use rayon::prelude::*;
 use structopt::StructOpt;
 use url::Url;
@@ -15,14 +35,24 @@ use std::env;
 use std::io::{self, Read};
 use std::ops::Deref;
 use std::path::PathBuf;
+use std::time::SystemTime;
 
-// (css Selector, node Handler) pair
-type SH<'a> = (&'a str, fn(&NodeDataRef<ElementData>));
+// (handler Name, css Selector, node Handler) triple
+type SH<'a> = (&'a str, &'a str, fn(&NodeDataRef<ElementData>));
 
 static OPT: Lazy<Opt> = Lazy::new(Opt::from_args);
 
+// where the input ended up after redirects, see Opt::get_base()
+static PAGE: OnceCell<Url> = OnceCell::new();
+
 fn main() -> Result<()> {
-    setup_panic!();
+    // human-panic still hands out `PanicInfo`
+    #[allow(deprecated)]
+    {
+        setup_panic!();
+    }
+
+    let captured = SystemTime::now();
 
     setup_log!{
         filter_level: OPT.log_level(),
@@ -33,69 +63,205 @@ fn main() -> Result<()> {
         .thread_name(|i| i.to_string())
         .build_global()?;
 
+    if let Some(Command::Check { ref file }) = OPT.cmd {
+        return check::run(file);
+    }
+
+    if let Some(ref path) = OPT.cookies {
+        http::COOKIES.set(http::CookieJar::open(path)?)
+                     .map_err(|_| Error::msg("cookies are already loaded"))?;
+    }
+
+    let netrc = match OPT.netrc_file {
+        Some(ref path) => Some(path.to_owned()),
+        None if OPT.netrc => http::default_netrc(),
+        None => None,
+    };
+
+    if let Some(ref path) = netrc {
+        http::NETRC.set(http::Netrc::open(path)?)
+                   .map_err(|_| Error::msg("netrc is already loaded"))?;
+    }
+
+    if OPT.cacert.is_some() || OPT.cert.is_some() || OPT.key.is_some() || OPT.insecure {
+        let config = tls::config(OPT.cacert.as_deref(), OPT.cert.as_deref(), OPT.key.as_deref(), OPT.insecure)?;
+        tls::CONFIG.set(config.into())
+                   .map_err(|_| Error::msg("TLS config is already set"))?;
+    }
+
+    if !OPT.filter_lists.is_empty() {
+        adblock::LISTS.set(adblock::load(&OPT.filter_lists)?)
+                      .map_err(|_| Error::msg("filter lists are already loaded"))?;
+    }
+
+    if let Some(ref path) = OPT.from_archive {
+        archive::ARCHIVE.set(archive::Archive::open(path)?)
+                        .map_err(|_| Error::msg("archive is already loaded"))?;
+    }
+
     let mut todo = vec![
         handler::base::TAG,
         handler::favicon::TAG,
     ];
 
+    // handlers turned off by a flag, only --list looks at them
+    let mut skip = vec![];
+
+    let js = [
+        handler::script::SCRIPT_TAG,
+        handler::script::LINK_TAG,
+        handler::script::LINK_JSON_TAG,
+    ];
+
     if OPT.js() {
-        todo.extend(&[
-            handler::script::SCRIPT_TAG,
-            handler::script::LINK_TAG,
-            handler::script::LINK_JSON_TAG,
-        ]);
+        todo.extend(&js);
+    } else {
+        skip.extend(js.iter().map(|h| (*h, "--no-js")));
     }
 
+    let css = [
+        handler::css::INTERN,
+        handler::css::EXTERN,
+        handler::css::INLINE,
+    ];
+
     if OPT.css() {
-        todo.extend(&[
-            handler::css::INTERN,
-            handler::css::EXTERN,
-            handler::css::INLINE,
-        ]);
+        todo.extend(&css);
+    } else {
+        skip.extend(css.iter().map(|h| (*h, "--no-css")));
     }
 
+    let img = [
+        handler::image::TAG,
+        handler::image::SRCSET_TAG,
+    ];
+
     if OPT.img() {
-        todo.extend(&[
-            handler::image::TAG,
-        ]);
+        todo.extend(&img);
+    } else {
+        skip.extend(img.iter().map(|h| (*h, "--no-img")));
     }
 
     let html = kuchiki::parse_html().one(get_input()?);
 
+    charset::declare_utf8(&html);
+
+    if OPT.sanitize {
+        sanitize::apply(&html);
+    } else if OPT.unwrap_noscript {
+        noscript::unwrap(&html);
+    }
+
+    if !OPT.no_lazy {
+        lazy::promote(&html, &OPT.lazy_attrs);
+    }
+
+    if OPT.img() {
+        srcset::collapse(&html, OPT.srcset);
+    }
+
+    adblock::apply(&html);
+    prune::apply(&html, &OPT.keep_only, &OPT.remove);
+
+    if OPT.reader {
+        reader::apply(&html);
+    }
+
+    // nothing would inline them, a `file://` link to the user's disk is no use to anyone else
+    let inject_css = if OPT.css() { &OPT.inject_css[..] } else { &[] };
+    let inject_js = if OPT.js() { &OPT.inject_js[..] } else { &[] };
+
+    if inject_css.len() < OPT.inject_css.len() || inject_js.len() < OPT.inject_js.len() {
+        log!(warn, "not injecting what --no-css/--no-js would leave as links");
+    }
+
+    let injected = inject::inject(&html, &env::current_dir()?, inject_css, inject_js)?;
+    filter::INJECTED.set(injected)
+                    .map_err(|_| Error::msg("injected files are already set"))?;
+
+    if OPT.list {
+        todo.extend(skip.iter().map(|(h, _)| h));
+        run(&todo, &html);
+
+        if !OPT.js() {
+            noscript::process(&html, |root| run(&todo, root));
+        }
+
+        let skip = skip.iter()
+                       .map(|((name, ..), flag)| (*name, *flag))
+                       .collect::<Vec<_>>();
+
+        return audit::print(&skip, OPT.list_format);
+    }
+
     run(&todo, &html);
+
+    // that is what readers see without scripts
+    if !OPT.js() {
+        noscript::process(&html, |root| run(&todo, root));
+    }
+
+    if let Some(ref path) = OPT.har {
+        har::save(path)?;
+    }
+
+    let failed = report::failed_count();
+
+    if !OPT.quiet || OPT.report.is_some() {
+        report::print(OPT.report)?;
+    }
+
+    if OPT.strict && failed > 0 {
+        bail!("{} resource(s) could not be inlined", failed);
+    }
+
+    lazy::strip_loading(&html);
+
+    if OPT.metadata {
+        let source = PAGE.get().cloned().or_else(|| OPT.get_page());
+        inject::stamp(&html, source.as_ref(), captured, report::inlined_count());
+    }
+
     save(html)?;
 
     Ok(())
 }
 
-fn run(todo: &[(&str, fn(&NodeDataRef<ElementData>))], html: &NodeRef) {
+fn run(todo: &[SH], html: &NodeRef) {
     todo.iter()
-        .flat_map(|(selector, handler)| {
+        .flat_map(|(name, selector, handler)| {
             html.select(selector)
                 .map_or(vec![], |v| v.collect())
                 .into_iter()
-                .map(move |node| UnsafeWrap::new((node, handler)))
+                .map(move |node| UnsafeWrap::new((node, name, handler)))
         })
         .collect::<Vec<_>>()
         .par_iter()
         .for_each(|w| {
-            let (node, handler) = w.deref();
-            log!(debug, "{}", utils::format_node(node));
-            handler(node);
+            let (node, name, handler) = w.deref();
+            let initiator = utils::Initiator::new(name, node);
+            log!(debug, "{}", initiator.node);
+            utils::with_initiator(Some(initiator), || handler(node));
         });
 }
 
 fn get_input() -> Result<String> {
-    match OPT.input {
+    match OPT.get_page() {
         Some(ref url) => {
-            utils::load_url(url)
-                  .and_then(|(_, data)|
-                        String::from_utf8(data).map_err(Error::msg))
+            let resp = utils::load_url(url)?;
+
+            if resp.url != *url {
+                log!(info, "{} redirected to {}", url, resp.url);
+            }
+
+            let _ = PAGE.set(resp.url);
+
+            Ok(charset::decode_html(&resp.data, &resp.mime))
         },
         None if atty::isnt(Stdin) => {
-            let mut buf = String::new();
-            io::stdin().read_to_string(&mut buf)?;
-            Ok(buf)
+            let mut buf = Vec::new();
+            io::stdin().read_to_end(&mut buf)?;
+            Ok(charset::decode_html(&buf, ""))
         },
         None => bail!("No file to process provided."),
     }
@@ -173,6 +339,193 @@ struct Opt {
     #[structopt(short = "I", long)]
     no_img: bool,
 
+    /// Seconds to wait for a connection
+    #[structopt(long, value_name = "SECS", default_value = "15")]
+    connect_timeout: u64,
+
+    /// Seconds to wait for data once connected
+    #[structopt(long, value_name = "SECS", default_value = "30")]
+    read_timeout: u64,
+
+    /// User-Agent sent with every request
+    #[structopt(short = "A", long, value_name = "UA", default_value = http::USER_AGENT)]
+    user_agent: String,
+
+    /// Extra header sent with every request ("Name: Value")
+    #[structopt(short = "H", long = "header", value_name = "HEADER", number_of_values = 1)]
+    headers: Vec<http::Header>,
+
+    /// Header sent to a host and its subdomains only ("host=Name: Value")
+    #[structopt(long = "host-header", value_name = "RULE", number_of_values = 1)]
+    host_headers: Vec<http::HostHeader>,
+
+    /// Proxy for every request (http://, socks5://...); HTTP(S)_PROXY and ALL_PROXY otherwise
+    #[structopt(long, value_name = "URL", validator = http::check_proxy)]
+    proxy: Option<String>,
+
+    /// Hosts to reach directly ("localhost,.example.com"); NO_PROXY otherwise
+    #[structopt(long, value_name = "HOSTS")]
+    no_proxy: Option<String>,
+
+    /// Trust the CA certificates in this PEM file as well
+    #[structopt(long, value_name = "FILE", parse(from_os_str))]
+    cacert: Option<PathBuf>,
+
+    /// Client certificate (PEM) for servers asking for one
+    #[structopt(long, value_name = "FILE", parse(from_os_str))]
+    cert: Option<PathBuf>,
+
+    /// Private key (PEM) for --cert, if it is not in the same file
+    #[structopt(long, value_name = "FILE", parse(from_os_str))]
+    key: Option<PathBuf>,
+
+    /// Do not verify TLS certificates
+    #[structopt(short = "k", long)]
+    insecure: bool,
+
+    /// Times to retry timeouts, resets, 429 and 5xx responses
+    #[structopt(long, value_name = "N", default_value = "2")]
+    retries: usize,
+
+    /// Milliseconds before the first retry, doubled for every next one
+    #[structopt(long, value_name = "MS", default_value = "500")]
+    retry_delay: u64,
+
+    /// Maximum parallel requests to one host, whatever --threads is
+    #[structopt(long, value_name = "N", default_value = "6")]
+    host_connections: usize,
+
+    /// Maximum requests per second to one host (0 is unlimited)
+    #[structopt(long, value_name = "N", default_value = "0")]
+    host_rate: f64,
+
+    /// Maximum number of redirects to follow
+    #[structopt(long, value_name = "N", default_value = "10")]
+    max_redirects: usize,
+
+    /// Basic auth credentials ("user:password") for the input's origin
+    #[structopt(short = "u", long, value_name = "USER:PASSWORD")]
+    user: Option<http::Credentials>,
+
+    /// Bearer token for the input's origin
+    #[structopt(long, value_name = "TOKEN")]
+    bearer: Option<String>,
+
+    /// Read credentials from ~/.netrc
+    #[structopt(short = "n", long)]
+    netrc: bool,
+
+    /// Read credentials from this netrc file
+    #[structopt(long, value_name = "FILE", parse(from_os_str))]
+    netrc_file: Option<PathBuf>,
+
+    /// Netscape cookies.txt to send cookies from
+    #[structopt(long, value_name = "FILE", parse(from_os_str))]
+    cookies: Option<PathBuf>,
+
+    /// Fetch URLs starting with PREFIX from TARGET, a directory or another URL
+    #[structopt(long = "map", value_name = "PREFIX=TARGET", number_of_values = 1)]
+    maps: Vec<rewrite::Rule>,
+
+    /// Only inline resources whose URL matches this glob (or regex with `re:`)
+    #[structopt(long, value_name = "PATTERN", number_of_values = 1)]
+    include: Vec<filter::Pattern>,
+
+    /// Never inline resources whose URL matches this glob (or regex with `re:`)
+    #[structopt(long, value_name = "PATTERN", number_of_values = 1)]
+    exclude: Vec<filter::Pattern>,
+
+    /// Only inline resources of this MIME type (e.g. "image/*")
+    #[structopt(long, value_name = "TYPE", number_of_values = 1)]
+    include_type: Vec<filter::Pattern>,
+
+    /// Never inline resources of this MIME type (e.g. "video/*")
+    #[structopt(long, value_name = "TYPE", number_of_values = 1)]
+    exclude_type: Vec<filter::Pattern>,
+
+    /// Drop elements matching this CSS selector before inlining
+    #[structopt(long, value_name = "SELECTOR", number_of_values = 1, validator = prune::check_selector)]
+    remove: Vec<String>,
+
+    /// Drop everything in <body> but elements matching this CSS selector before inlining
+    #[structopt(long, value_name = "SELECTOR", number_of_values = 1, validator = prune::check_selector)]
+    keep_only: Vec<String>,
+
+    /// Remove scripts, event handlers and `javascript:` URLs, show <noscript> content instead
+    #[structopt(long)]
+    sanitize: bool,
+
+    /// Put <noscript> content in the page instead of inlining it inside <noscript> with --no-js
+    #[structopt(long)]
+    unwrap_noscript: bool,
+
+    /// Lazy-loading attributes moved to where they belong before inlining (src, srcset, poster, style)
+    #[structopt(long, value_name = "ATTR=TARGET", use_delimiter = true, default_value = lazy::DEFAULT)]
+    lazy_attrs: Vec<lazy::Rule>,
+
+    /// Leave lazy-loading attributes alone
+    #[structopt(long)]
+    no_lazy: bool,
+
+    /// Which srcset and CSS image-set() images to keep (all, largest, smallest, dpr:N, width:N)
+    #[structopt(long, value_name = "POLICY", default_value = "all")]
+    srcset: srcset::Policy,
+
+    /// Keep only the article: title, byline and main content
+    #[structopt(long)]
+    reader: bool,
+
+    /// Add this stylesheet (file or URL) to the page
+    #[structopt(long, value_name = "CSS", number_of_values = 1)]
+    inject_css: Vec<String>,
+
+    /// Add this script (file or URL) to the page
+    #[structopt(long, value_name = "JS", number_of_values = 1)]
+    inject_js: Vec<String>,
+
+    /// Stamp the output with source URL, capture time, tool version and resource count
+    #[structopt(long)]
+    metadata: bool,
+
+    /// Adblock Plus/EasyList filter list (file or URL) to block resources and hide elements with
+    #[structopt(long = "filter-list", value_name = "LIST", number_of_values = 1)]
+    filter_lists: Vec<String>,
+
+    /// Leave resources bigger than this as links (e.g. 200K, 2M)
+    #[structopt(long, value_name = "SIZE", parse(try_from_str = filter::parse_size))]
+    max_size: Option<usize>,
+
+    /// Serve requests from a WARC or HAR capture instead of the network
+    #[structopt(long, value_name = "FILE", parse(from_os_str))]
+    from_archive: Option<PathBuf>,
+
+    /// What to do with URLs missing from the archive (fail, network)
+    #[structopt(long, value_name = "FALLBACK", default_value = "fail")]
+    archive_fallback: archive::Fallback,
+
+    /// Write a HAR log of every fetched resource
+    #[structopt(long, value_name = "FILE", parse(from_os_str))]
+    har: Option<PathBuf>,
+
+    /// List resources that would be inlined without fetching them
+    #[structopt(long, alias = "dry-run")]
+    list: bool,
+
+    /// Format of the --list output (table, json)
+    #[structopt(long, value_name = "FORMAT", default_value = "table")]
+    list_format: audit::Format,
+
+    /// Print a summary of inlined and failed resources to stderr (text, json)
+    #[structopt(long, value_name = "FORMAT")]
+    report: Option<report::Format>,
+
+    /// Fail without writing anything if some resource could not be inlined
+    #[structopt(long)]
+    strict: bool,
+
+    #[structopt(subcommand)]
+    cmd: Option<Command>,
+
     /// Minify HTML
     #[cfg(all(feature="minify-html", not(feature="esbuild")))]
     #[structopt(short = "m", long)]
@@ -184,18 +537,40 @@ struct Opt {
     minify: bool,
 }
 
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Report network references left in an inlined page and fail if there are any
+    Check {
+        /// File or URL to verify
+        #[structopt(parse(from_str = Opt::parse_url))]
+        file: Url,
+    },
+}
+
 impl Opt {
     fn get_base(&self) -> Url {
-        self.input
-            .as_ref()
-            .map(|u| u.join("./").unwrap_or(u.to_owned()))
+        PAGE.get()
+            .cloned()
+            .or_else(|| self.get_page())
+            .map(|u| u.join("./").unwrap_or(u))
             .unwrap_or(self.cwd.clone())
     }
 
+    // Input URL without `user:pass@`, credentials go to http::credentials()
+    fn get_page(&self) -> Option<Url> {
+        self.input
+            .clone()
+            .map(|mut u| {
+                let _ = u.set_username("");
+                let _ = u.set_password(None);
+                u
+            })
+    }
+
     fn parse_url(input: &str) -> Url {
         Url::parse(input)
             .or_else(|_| Opt::read_cwd().join(input))
-            .expect(&format!("Cannot parse FILE/URL: {}", input))
+            .unwrap_or_else(|_| panic!("Cannot parse FILE/URL: {}", input))
     }
 
     fn read_cwd() -> Url {
@@ -0,0 +1,85 @@
+use html5ever::{interface::QualName, local_name, namespace_url, ns};
+use kuchiki::NodeRef;
+use kuchiki::traits::*;
+
+/// Puts what is in `<noscript>` (kuchiki keeps it as text) in its place, parsed
+pub fn unwrap(html: &NodeRef) {
+    for noscript in all(html) {
+        if let Some(root) = parse(&noscript) {
+            for child in root.children().collect::<Vec<_>>() {
+                noscript.insert_before(child);
+            }
+        }
+
+        noscript.detach();
+    }
+}
+
+/// Lets `run` inline what is in each `<noscript>`, which stays a `<noscript>`
+pub fn process(html: &NodeRef, run: impl Fn(&NodeRef)) {
+    for noscript in all(html) {
+        let root = match parse(&noscript) {
+            Some(root) => root,
+            None => continue,
+        };
+
+        run(&root);
+
+        let content = root.children()
+                          .map(|child| child.to_string())
+                          .collect::<String>();
+
+        for child in noscript.children().collect::<Vec<_>>() {
+            child.detach();
+        }
+
+        noscript.append(NodeRef::new_text(content));
+    }
+}
+
+fn all(html: &NodeRef) -> Vec<NodeRef> {
+    html.select("noscript")
+        .map_or(vec![], |v| v.map(|elm| elm.as_node().clone()).collect())
+}
+
+// a fragment is parsed into a lone <html>, in the context of where the <noscript> is
+fn parse(noscript: &NodeRef) -> Option<NodeRef> {
+    let context = noscript.parent()
+                          .and_then(|p| p.into_element_ref())
+                          .map_or_else(|| QualName::new(None, ns!(html), local_name!("body")), |p| p.name.clone());
+
+    kuchiki::parse_fragment(context, vec![])
+        .one(noscript.text_contents())
+        .first_child()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE: &str = r#"<div><img src="placeholder.gif"><noscript><img src="real.jpg"><p>no JS</p></noscript></div>"#;
+
+    #[test]
+    fn unwrap_content() {
+        let html = kuchiki::parse_html().one(PAGE);
+        unwrap(&html);
+
+        assert_eq!(html.select_first("div").unwrap().as_node().to_string(),
+                   r#"<div><img src="placeholder.gif"><img src="real.jpg"><p>no JS</p></div>"#);
+    }
+
+    #[test]
+    fn process_content() {
+        let html = kuchiki::parse_html().one(PAGE);
+
+        process(&html, |root| {
+            for img in root.select("img").unwrap() {
+                img.attributes.borrow_mut().insert("src", "data:,".to_owned());
+            }
+        });
+
+        // the page itself is not touched
+        assert_eq!(html.select_first("div").unwrap().as_node().to_string(),
+                   r#"<div><img src="placeholder.gif"><noscript><img src="data:,"><p>no JS</p></noscript></div>"#);
+    }
+}
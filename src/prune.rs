@@ -0,0 +1,106 @@
+use kuchiki::{NodeRef, Selectors};
+
+/// Validator for --remove and --keep-only
+pub fn check_selector(selector: String) -> Result<(), String> {
+    Selectors::compile(&selector)
+        .map(drop)
+        .map_err(|_| format!("`{}` is not a CSS selector kuchiki understands", selector))
+}
+
+/// Cuts the page down to `keep_only` (if given), then drops `remove`;
+/// handlers never see what is gone, so nothing is fetched for it
+pub fn apply(html: &NodeRef, keep_only: &[String], remove: &[String]) {
+    if !keep_only.is_empty() {
+        keep(html, &keep_only.join(", "));
+    }
+
+    for selector in remove {
+        let found = html.select(selector)
+                        .map_or(vec![], |v| v.collect());
+
+        log!(info, "removing {} element(s) matching `{}`", found.len(), selector);
+
+        found.iter().for_each(|elm| elm.as_node().detach());
+    }
+}
+
+// Everything in <body> that is not, contains or is inside a match goes away;
+// <head> stays for the styles
+fn keep(html: &NodeRef, selector: &str) {
+    let kept = html.select(selector)
+                   .map_or(vec![], |v| v.collect());
+
+    if kept.is_empty() {
+        log!(warn, "nothing matches --keep-only `{}`, keeping the whole page", selector);
+        return;
+    }
+
+    let body = match html.select_first("body") {
+        Ok(body) => body.as_node().clone(),
+        Err(_) => return,
+    };
+
+    let kept = kept.iter()
+                   .map(|elm| elm.as_node().clone())
+                   .collect::<Vec<_>>();
+
+    let on_path = kept.iter()
+                      .flat_map(|node| node.ancestors())
+                      .collect::<Vec<_>>();
+
+    prune(&body, &kept, &on_path);
+}
+
+fn prune(node: &NodeRef, kept: &[NodeRef], on_path: &[NodeRef]) {
+    for child in node.children().collect::<Vec<_>>() {
+        if kept.contains(&child) {
+            continue;
+        }
+
+        match on_path.contains(&child) {
+            true => prune(&child, kept, on_path),
+            false => child.detach(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use kuchiki::traits::*;
+
+    #[test]
+    fn prune_page() {
+        let page = r#"<html><head><title>t</title></head><body>
+            <nav><a href="/">home</a></nav>
+            <main>
+                <h1>Title</h1>
+                <article><p>text</p><div class="comments">c</div></article>
+            </main>
+            <div id="cookie-banner">cookies</div>
+        </body></html>"#;
+
+        let body = |keep_only: &[&str], remove: &[&str]| {
+            let strings = |s: &[&str]| s.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+            let html = kuchiki::parse_html().one(page);
+
+            apply(&html, &strings(keep_only), &strings(remove));
+
+            let body = html.select_first("body").unwrap().as_node().to_string();
+            body.split_whitespace().collect::<Vec<_>>().join(" ")
+        };
+
+        assert_eq!(body(&[], &["nav", "#cookie-banner", ".comments"]),
+                   "<body> <main> <h1>Title</h1> <article><p>text</p></article> </main> </body>");
+        assert_eq!(body(&["article"], &[".comments"]),
+                   "<body><main><article><p>text</p></article></main></body>");
+        assert_eq!(body(&["h1", "article p"], &[]),
+                   "<body><main><h1>Title</h1><article><p>text</p></article></main></body>");
+
+        // a selector nothing matches must not leave an empty page
+        assert!(body(&["#missing"], &[]).contains("cookies"));
+
+        assert!(check_selector("div:has(> p".to_owned()).is_err());
+    }
+}
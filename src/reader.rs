@@ -0,0 +1,362 @@
+use html5ever::{interface::QualName, namespace_url, ns};
+use kuchiki::{Attribute, ExpandedName, Node, NodeRef};
+use kuchiki::iter::NodeIterator;
+use kuchiki::traits::*;
+
+use std::collections::HashMap;
+
+// class/id of site chrome rather than content
+regex!(static RE_UNLIKELY, r#"(?i)-ad-|ai2html|banner|breadcrumbs|combx|comment|community|cover-wrap|disqus|extra|footer|gdpr|header|legends|menu|related|remark|replies|rss|shoutbox|sidebar|skyscraper|social|sponsor|supplemental|ad-break|agegate|pagination|pager|popup|yom-remote"#);
+regex!(static RE_MAYBE, r#"(?i)and|article|body|column|content|main|shadow"#);
+
+regex!(static RE_POSITIVE, r#"(?i)article|body|content|entry|hentry|h-entry|main|page|post|text|blog|story"#);
+regex!(static RE_NEGATIVE, r#"(?i)-ad-|hidden|^hid$| hid$| hid |^hid |banner|combx|comment|com-|contact|foot|footer|footnote|gdpr|masthead|media|meta|outbrain|promo|related|scroll|share|shoutbox|sidebar|skyscraper|sponsor|shopping|tags|tool|widget"#);
+
+// `Title | Site`, `Title - Site`
+regex!(static RE_TITLE_SEPARATOR, r#"\s[|\-–—:»]\s"#);
+
+/// Never part of an article
+const JUNK: &str = "script, style, nav, aside, footer, form, button, input, select, textarea, iframe, object, embed";
+
+/// What reader mode keeps of a page
+pub struct Article {
+    pub title: Option<String>,
+    pub byline: Option<String>,
+    pub content: NodeRef,
+}
+
+/// Replaces the page with its title, byline and main content; the rest isn't fetched
+pub fn apply(html: &NodeRef) {
+    let article = match extract(html) {
+        Some(article) => article,
+        None => {
+            log!(warn, "no article found, keeping the whole page");
+            return;
+        }
+    };
+
+    if let Ok(head) = html.select_first("head") {
+        // the charset, <base> and icons are still useful
+        for child in head.as_node().children().collect::<Vec<_>>() {
+            let keep = child.as_element().is_some_and(|elm| {
+                let attrs = elm.attributes.borrow();
+                match elm.name.local.as_ref() {
+                    "base" => true,
+                    "meta" => attrs.contains("charset"),
+                    "link" => attrs.get("rel").is_some_and(|rel| rel.split_ascii_whitespace().any(|r| r.eq_ignore_ascii_case("icon"))),
+                    _ => false,
+                }
+            });
+
+            if !keep {
+                child.detach();
+            }
+        }
+
+        if let Some(ref title) = article.title {
+            head.as_node().append(element("title", &[], Some(title)));
+        }
+    }
+
+    let body = match html.select_first("body") {
+        Ok(body) => body.as_node().clone(),
+        Err(_) => return,
+    };
+
+    for child in body.children().collect::<Vec<_>>() {
+        child.detach();
+    }
+
+    let wrapper = element("article", &[], None);
+
+    if let Some(ref title) = article.title {
+        wrapper.append(element("h1", &[], Some(title)));
+    }
+
+    if let Some(ref byline) = article.byline {
+        wrapper.append(element("p", &[("class", "byline")], Some(byline)));
+    }
+
+    wrapper.append(article.content);
+    body.append(wrapper);
+}
+
+/// Readability-like: scores blocks by the paragraphs in them, their text and
+/// link density and their tags and classes, then takes the best with its kin
+pub fn extract(html: &NodeRef) -> Option<Article> {
+    let title = title(html);
+    let byline = byline(html);
+
+    let body = html.select_first("body").ok()?.as_node().clone();
+
+    // work on a copy, the page is only touched once there is something to replace it with
+    let body = kuchiki::parse_html().one(body.to_string()).select_first("body").ok()?.as_node().clone();
+
+    clean(&body);
+
+    let mut scores: HashMap<*const Node, (NodeRef, f64)> = HashMap::new();
+
+    for p in body.select("p, pre, td, blockquote, li").ok()? {
+        let text = p.text_contents();
+        let len = text.trim().chars().count();
+
+        if len < 25 {
+            continue;
+        }
+
+        let score = 1.0 + text.matches(',').count() as f64 + (len as f64 / 100.0).min(3.0);
+
+        for (level, ancestor) in p.as_node().ancestors().take(3).enumerate() {
+            if ancestor.as_element().is_none_or(|elm| &*elm.name.local == "html") {
+                break;
+            }
+
+            let entry = scores.entry(&*ancestor as *const Node)
+                              .or_insert_with(|| (ancestor.clone(), initial_score(&ancestor)));
+
+            entry.1 += match level {
+                0 => score,
+                1 => score / 2.0,
+                _ => score / 6.0,
+            };
+        }
+    }
+
+    let (top, top_score) = scores.values()
+                                 .map(|(node, score)| (node.clone(), score * (1.0 - link_density(node))))
+                                 .max_by(|a, b| a.1.total_cmp(&b.1))?;
+
+    log!(info, "article is {} scoring {:.1}", describe(&top), top_score);
+
+    let content = element("div", &[], None);
+    let threshold = (top_score * 0.2).max(10.0);
+
+    // siblings that score well too, or look like paragraphs of the same text
+    for sibling in top.parent().map_or_else(|| vec![top.clone()], |p| p.children().collect()) {
+        let keep = sibling == top
+            || scores.get(&(&*sibling as *const Node))
+                     .is_some_and(|(node, score)| score * (1.0 - link_density(node)) >= threshold)
+            || sibling.as_element().is_some_and(|elm| {
+                   let len = sibling.text_contents().trim().chars().count();
+                   &*elm.name.local == "p" && len > 80 && link_density(&sibling) < 0.25
+               });
+
+        if keep {
+            content.append(sibling);
+        }
+    }
+
+    prune_blocks(&content);
+
+    // the title is on top already
+    if let (Some(title), Ok(heading)) = (&title, content.select_first("h1, h2")) {
+        if heading.text_contents().trim() == title {
+            heading.as_node().detach();
+        }
+    }
+
+    Some(Article {
+        title,
+        byline,
+        content,
+    })
+}
+
+// scripts, forms and blocks that look like site chrome by class or id
+fn clean(body: &NodeRef) {
+    body.select(JUNK)
+        .map_or(vec![], |v| v.collect())
+        .iter()
+        .for_each(|elm| elm.as_node().detach());
+
+    body.descendants()
+        .elements()
+        .collect::<Vec<_>>()
+        .into_iter()
+        .filter(|elm| !matches!(elm.name.local.as_ref(), "body" | "article" | "main" | "a"))
+        .filter(|elm| {
+            let names = class_and_id(elm.as_node());
+            RE_UNLIKELY.is_match(&names) && !RE_MAYBE.is_match(&names)
+        })
+        .for_each(|elm| elm.as_node().detach());
+}
+
+// lists of links and negative blocks inside the article
+fn prune_blocks(content: &NodeRef) {
+    content.select("div, section, ul, ol, table")
+           .map_or(vec![], |v| v.collect())
+           .into_iter()
+           .filter(|elm| {
+               let node = elm.as_node();
+               let len = node.text_contents().trim().chars().count();
+               let images = node.select("img").map_or(0, |v| v.count());
+
+               class_weight(node) < 0.0
+                   || (len < 200 && images == 0 && link_density(node) > 0.5)
+           })
+           .for_each(|elm| elm.as_node().detach());
+}
+
+fn initial_score(node: &NodeRef) -> f64 {
+    let tag = node.as_element().map_or(0.0, |elm| match elm.name.local.as_ref() {
+        "article" => 10.0,
+        "main" | "section" | "div" => 5.0,
+        "pre" | "td" | "blockquote" => 3.0,
+        "address" | "ol" | "ul" | "dl" | "dd" | "dt" | "li" | "form" => -3.0,
+        "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "th" => -5.0,
+        _ => 0.0,
+    });
+
+    tag + class_weight(node)
+}
+
+fn class_weight(node: &NodeRef) -> f64 {
+    let names = class_and_id(node);
+
+    match (RE_POSITIVE.is_match(&names), RE_NEGATIVE.is_match(&names)) {
+        (true, false) => 25.0,
+        (false, true) => -25.0,
+        _ => 0.0,
+    }
+}
+
+fn class_and_id(node: &NodeRef) -> String {
+    node.as_element().map_or_else(String::new, |elm| {
+        let attrs = elm.attributes.borrow();
+        format!("{} {}", attrs.get("class").unwrap_or_default(), attrs.get("id").unwrap_or_default())
+    })
+}
+
+// share of the text that is in links
+fn link_density(node: &NodeRef) -> f64 {
+    let len = node.text_contents().trim().chars().count();
+
+    if len == 0 {
+        return 0.0;
+    }
+
+    let links = node.select("a")
+                    .map_or(0, |v| v.map(|a| a.text_contents().trim().chars().count()).sum::<usize>());
+
+    links as f64 / len as f64
+}
+
+fn title(html: &NodeRef) -> Option<String> {
+    let meta = html.select_first(r#"meta[property="og:title"], meta[name="twitter:title"]"#)
+                   .ok()
+                   .and_then(|m| m.attributes.borrow().get("content").map(str::to_owned));
+
+    let title = meta.or_else(|| html.select_first("title").ok().map(|t| t.text_contents()))
+                    .or_else(|| html.select_first("h1").ok().map(|t| t.text_contents()))?;
+
+    let title = title.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    // drop the site name if what is left still reads like a title
+    let title = match RE_TITLE_SEPARATOR.find_iter(&title).last() {
+        Some(sep) if title[..sep.start()].split_whitespace().count() >= 3 => title[..sep.start()].to_owned(),
+        _ => title,
+    };
+
+    Some(title).filter(|t| !t.is_empty())
+}
+
+fn byline(html: &NodeRef) -> Option<String> {
+    let meta = html.select_first(r#"meta[name="author"]"#)
+                   .ok()
+                   .and_then(|m| m.attributes.borrow().get("content").map(str::to_owned));
+
+    meta.or_else(|| {
+            html.select_first(r#"[rel="author"], [itemprop="author"], .byline, .author"#)
+                .ok()
+                .map(|elm| elm.text_contents())
+        })
+        .map(|b| b.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|b| !b.is_empty() && b.chars().count() < 100)
+}
+
+fn describe(node: &NodeRef) -> String {
+    node.as_element().map_or_else(String::new, |elm| {
+        format!("<{} {}>", elm.name.local, class_and_id(node).trim())
+    })
+}
+
+fn element(name: &str, attributes: &[(&str, &str)], text: Option<&str>) -> NodeRef {
+    let elm = NodeRef::new_element(
+        QualName::new(None, ns!(html), name.into()),
+        attributes.iter().map(|(k, v)| (
+            ExpandedName::new("", *k),
+            Attribute {
+                prefix: None,
+                value: v.to_string(),
+            },
+        )));
+
+    if let Some(text) = text {
+        elm.append(NodeRef::new_text(text));
+    }
+
+    elm
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE: &str = r##"<html><head>
+        <title>How the river got its name, and other stories | The Daily Example</title>
+        <meta name="author" content="Jane Roe">
+        <link rel="stylesheet" href="site.css">
+        <script src="tracking.js"></script>
+    </head><body>
+        <header class="site-header"><a href="/">The Daily Example</a></header>
+        <nav><a href="/news">News</a> <a href="/sport">Sport</a></nav>
+        <div id="main-column">
+            <div class="story-body">
+                <h1>How the river got its name, and other stories</h1>
+                <p>The river was named long ago, by people who lived along its banks, fished in it and crossed it every day.</p>
+                <p>Nobody remembers who said it first, but the name stuck, and by the time maps were drawn it was on all of them.</p>
+                <p>Today the name is <a href="/river">on signs</a>, on bridges and on the lips of every child who grows up nearby.</p>
+                <ul class="share-tools"><li><a href="#">Share</a></li><li><a href="#">Tweet</a></li></ul>
+            </div>
+            <div class="comments"><p>First! This comment is long enough to be scored, with commas, too.</p></div>
+        </div>
+        <aside class="sidebar"><p>Most read: a story about something else entirely, long enough to count.</p></aside>
+        <footer>Copyright, all rights reserved, no part of this page may be used without permission.</footer>
+    </body></html>"##;
+
+    #[test]
+    fn extract_article() {
+        let html = kuchiki::parse_html().one(PAGE);
+        let article = extract(&html).unwrap();
+
+        assert_eq!(article.title.as_deref(), Some("How the river got its name, and other stories"));
+        assert_eq!(article.byline.as_deref(), Some("Jane Roe"));
+
+        let text = article.content.text_contents();
+        assert!(text.contains("The river was named long ago"));
+        assert!(text.contains("on signs"));
+        assert!(!text.contains("First!"));
+        assert!(!text.contains("Most read"));
+        assert!(!text.contains("Tweet"));
+        assert!(!text.contains("Copyright"));
+        assert!(article.content.select_first("h1").is_err());
+    }
+
+    #[test]
+    fn replace_page() {
+        let html = kuchiki::parse_html().one(PAGE);
+        apply(&html);
+
+        let head = html.select_first("head").unwrap().as_node().to_string();
+        assert_eq!(head, "<head><title>How the river got its name, and other stories</title></head>");
+
+        let article = html.select_first("body > article").unwrap();
+        let children = article.as_node()
+                              .children()
+                              .elements()
+                              .map(|elm| elm.name.local.to_string())
+                              .collect::<Vec<_>>();
+
+        assert_eq!(children, ["h1", "p", "div"]);
+    }
+}
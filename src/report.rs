@@ -0,0 +1,191 @@
+use crate::utils;
+
+use anyhow::{Result, bail};
+use once_cell::sync::Lazy;
+use serde_json::json;
+
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static INLINED: AtomicUsize = AtomicUsize::new(0);
+static FAILURES: Lazy<Mutex<Vec<Failure>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+/// Why a resource could not be loaded
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoadError {
+    Url(String),
+    Fetch(String),
+    Status(i32),
+    Decode(String),
+    Scheme(String),
+    NotArchived,
+    Filtered(String),
+    Blocked(String),
+    Node(String),
+}
+
+impl LoadError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            LoadError::Url(_) => "url",
+            LoadError::Fetch(_) => "fetch",
+            LoadError::Status(_) => "status",
+            LoadError::Decode(_) => "decode",
+            LoadError::Scheme(_) => "scheme",
+            LoadError::NotArchived => "archive",
+            LoadError::Filtered(_) => "filtered",
+            LoadError::Blocked(_) => "blocked",
+            LoadError::Node(_) => "node",
+        }
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Url(e) => write!(f, "invalid URL: {}", e),
+            LoadError::Fetch(e) => write!(f, "fetch failed: {}", e),
+            LoadError::Status(code) => write!(f, "response status code: {}", code),
+            LoadError::Decode(e) => write!(f, "cannot decode: {}", e),
+            LoadError::Scheme(s) => write!(f, "not supported URL scheme `{}`", s),
+            LoadError::NotArchived => write!(f, "not in the archive"),
+            LoadError::Filtered(e) => write!(f, "filtered out: {}", e),
+            LoadError::Blocked(rule) => write!(f, "blocked by filter rule `{}`", rule),
+            LoadError::Node(e) => write!(f, "cannot access the element: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            _ => bail!("unknown format `{}` (use `text` or `json`)", s),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Failure {
+    url: String,
+    kind: &'static str,
+    error: String,
+    handler: Option<String>,
+    node: Option<String>,
+}
+
+impl Failure {
+    fn skipped(&self) -> bool {
+        self.kind == "filtered" || self.kind == "blocked"
+    }
+}
+
+pub fn inlined() {
+    INLINED.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn inlined_count() -> usize {
+    INLINED.load(Ordering::Relaxed)
+}
+
+/// Remembers a resource that could not be inlined, along with who asked for it
+pub fn failed(url: &str, err: &anyhow::Error) {
+    let kind = err.downcast_ref::<LoadError>()
+                  .map_or("fetch", LoadError::kind);
+
+    log!(info, "cannot load {}: {}", url, err);
+
+    let initiator = utils::initiator();
+
+    FAILURES.lock()
+            .expect("cannot reach failures")
+            .push(Failure {
+                url: url.to_owned(),
+                kind,
+                error: err.to_string(),
+                handler: initiator.as_ref().map(|i| i.handler.to_owned()),
+                node: initiator.map(|i| i.node),
+            });
+}
+
+/// Resources left out on purpose (--exclude, filter lists) don't count
+pub fn failed_count() -> usize {
+    FAILURES.lock()
+            .expect("cannot reach failures")
+            .iter()
+            .filter(|f| !f.skipped())
+            .count()
+}
+
+/// Summary goes to stderr; text one is only shown if something failed
+pub fn print(format: Option<Format>) -> io::Result<()> {
+    let mut failures = std::mem::take(&mut *FAILURES.lock().expect("cannot reach failures"));
+    failures.sort_by(|a, b| a.url.cmp(&b.url));
+
+    let (skipped, failures): (Vec<_>, Vec<_>) = failures.into_iter().partition(Failure::skipped);
+
+    let stderr = io::stderr();
+    let mut out = stderr.lock();
+
+    match format {
+        Some(Format::Json) => {
+            let report = json!({
+                "inlined": inlined_count(),
+                "failed": failures.len(),
+                "skipped": skipped.len(),
+                "failures": failures.iter().map(to_json).collect::<Vec<_>>(),
+                "skips": skipped.iter().map(to_json).collect::<Vec<_>>(),
+            });
+
+            serde_json::to_writer_pretty(&mut out, &report)?;
+            writeln!(out)
+        }
+        Some(Format::Text) => print_text(&mut out, &failures, &skipped),
+        None if !failures.is_empty() => print_text(&mut out, &failures, &skipped),
+        None => Ok(()),
+    }
+}
+
+fn to_json(f: &Failure) -> serde_json::Value {
+    json!({
+        "url": f.url,
+        "kind": f.kind,
+        "error": f.error,
+        "handler": f.handler,
+        "node": f.node,
+    })
+}
+
+fn print_text(out: &mut impl Write, failures: &[Failure], skipped: &[Failure]) -> io::Result<()> {
+    write!(out, "{} resource(s) inlined, {} failed", inlined_count(), failures.len())?;
+
+    if !skipped.is_empty() {
+        write!(out, ", {} skipped", skipped.len())?;
+    }
+
+    writeln!(out)?;
+
+    for f in failures.iter().chain(skipped) {
+        writeln!(out, "  [{}] {}: {}", f.kind, f.url, f.error)?;
+
+        if let (Some(handler), Some(node)) = (&f.handler, &f.node) {
+            writeln!(out, "      by {} {}", handler, node)?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,81 @@
+use anyhow::{Result, anyhow, bail};
+use url::Url;
+
+use std::env;
+use std::str::FromStr;
+
+/// `PREFIX=TARGET` as given to --map, URLs starting with `PREFIX` are fetched from `TARGET`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    pub prefix: String,
+    pub target: String,
+}
+
+impl FromStr for Rule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (prefix, target) = match s.split_once('=') {
+            Some((prefix, target)) if !prefix.is_empty() && !target.is_empty() => (prefix, target),
+            _ => bail!("expected `PREFIX=TARGET`, got `{}`", s),
+        };
+
+        let prefix = Url::parse(prefix)
+            .map_err(|e| anyhow!("`{}` is not a URL: {}", prefix, e))?;
+
+        // anything that is not a URL is a local path
+        let mut target = match Url::parse(target) {
+            Ok(url) if url.scheme().len() > 1 => url.to_string(),
+            _ => {
+                let cwd = env::current_dir()?;
+                Url::from_file_path(cwd.join(target))
+                    .map_err(|_| anyhow!("cannot turn `{}` into a file URL", target))?
+                    .to_string()
+            }
+        };
+
+        // a directory maps to a directory, even if the slash is forgotten
+        if prefix.as_str().ends_with('/') && !target.ends_with('/') {
+            target.push('/');
+        }
+
+        Ok(Rule {
+            prefix: prefix.to_string(),
+            target,
+        })
+    }
+}
+
+/// Where `url` should really come from; the longest matching prefix wins
+pub fn apply(rules: &[Rule], url: &Url) -> Option<Url> {
+    let url = url.as_str();
+
+    rules.iter()
+         .filter(|r| url.starts_with(&r.prefix))
+         .max_by_key(|r| r.prefix.len())
+         .and_then(|r| Url::parse(&format!("{}{}", r.target, &url[r.prefix.len()..])).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn map_prefixes() {
+        let rules = [
+            "https://cdn.example.com/=/srv/vendor".parse::<Rule>().unwrap(),
+            "https://cdn.example.com/fonts/=http://localhost:8000/fonts/".parse().unwrap(),
+        ];
+
+        let apply = |url: &str| apply(&rules, &Url::parse(url).unwrap()).map(String::from);
+
+        assert_eq!(apply("https://cdn.example.com/jquery/3.6/jquery.min.js?v=1").as_deref(),
+                   Some("file:///srv/vendor/jquery/3.6/jquery.min.js?v=1"));
+        assert_eq!(apply("https://cdn.example.com/fonts/a.woff2").as_deref(),
+                   Some("http://localhost:8000/fonts/a.woff2"));
+        assert_eq!(apply("https://example.com/a.css"), None);
+
+        assert!("https://cdn.example.com/".parse::<Rule>().is_err());
+        assert!("cdn.example.com=vendor".parse::<Rule>().is_err());
+    }
+}
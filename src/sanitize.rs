@@ -0,0 +1,129 @@
+use crate::noscript;
+
+use kuchiki::NodeRef;
+use kuchiki::iter::NodeIterator;
+
+// attributes that take a URL, and so may take a `javascript:` one
+const URL_ATTRIBUTES: &[&str] = &["href", "src", "action", "formaction", "data", "poster", "background", "cite", "ping", "lowsrc"];
+
+// `<script type=application/ld+json>` is data, it never runs
+const DATA_SCRIPTS: &[&str] = &["application/ld+json", "application/json"];
+
+// SVG animations can set any attribute, `href` included
+const ANIMATION_VALUES: &[&str] = &["from", "to", "values"];
+
+/// Makes the page static: no scripts, plugins, refreshes, event handlers, `srcdoc`s
+/// or `javascript:` URLs, and `<noscript>` fallbacks shown instead
+pub fn apply(html: &NodeRef) {
+    noscript::unwrap(html);
+
+    let scripts = html.select("script, link[rel=modulepreload], link[rel=preload][as=script], link[type]")
+                      .map_or(vec![], |v| v.collect())
+                      .into_iter()
+                      .filter(|elm| {
+                          let attrs = elm.attributes.borrow();
+                          let kind = attrs.get("type").unwrap_or_default().to_ascii_lowercase();
+
+                          match elm.name.local.as_ref() {
+                              "script" => !DATA_SCRIPTS.contains(&kind.as_str()),
+                              _ => kind.is_empty() || kind.contains("javascript") || kind.contains("ecmascript"),
+                          }
+                      })
+                      .collect::<Vec<_>>();
+
+    log!(info, "removing {} script(s)", scripts.len());
+
+    scripts.iter().for_each(|elm| elm.as_node().detach());
+
+    let active = html.select("embed, object, meta[http-equiv], animate, set")
+                     .map_or(vec![], |v| v.collect())
+                     .into_iter()
+                     .filter(|elm| {
+                         let attrs = elm.attributes.borrow();
+
+                         match elm.name.local.as_ref() {
+                             "meta" => attrs.get("http-equiv").unwrap_or_default().trim().eq_ignore_ascii_case("refresh"),
+                             "animate" | "set" => ANIMATION_VALUES.iter()
+                                                                  .filter_map(|a| attrs.get(*a))
+                                                                  .flat_map(|v| v.split(';'))
+                                                                  .any(is_javascript),
+                             _ => true,
+                         }
+                     })
+                     .collect::<Vec<_>>();
+
+    log!(info, "removing {} plugin(s), refresh(es) and animation(s)", active.len());
+
+    active.iter().for_each(|elm| elm.as_node().detach());
+
+    for elm in html.descendants().elements() {
+        elm.attributes.borrow_mut().map.retain(|name, attr| {
+            let handler = name.local.len() > 2 && name.local.to_ascii_lowercase().starts_with("on");
+            let js = URL_ATTRIBUTES.contains(&name.local.as_ref()) && is_javascript(&attr.value);
+
+            !(handler || js || name.local.as_ref() == "srcdoc")
+        });
+    }
+}
+
+// browsers skip leading whitespace and control characters, and tabs and newlines anywhere
+fn is_javascript(url: &str) -> bool {
+    let url = url.trim_start_matches(|c: char| c <= ' ')
+                 .chars()
+                 .filter(|c| !matches!(c, '\t' | '\n' | '\r'))
+                 .take("javascript:".len())
+                 .collect::<String>();
+
+    url.eq_ignore_ascii_case("javascript:")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use kuchiki::traits::*;
+
+    #[test]
+    fn static_page() {
+        let html = kuchiki::parse_html().one(r#"<html><head>
+            <script src="https://example.com/app.js"></script>
+            <script type="application/ld+json">{"@type": "Article"}</script>
+            <link rel="preload" as="script" href="app.js">
+            <link rel="stylesheet" href="site.css">
+            <noscript><link rel="stylesheet" href="noscript.css"></noscript>
+            <meta http-equiv=" Refresh " content="0; url=javascript:alert(1)">
+            <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        </head><body onload="init()">
+            <a href=" java&#x09;script:alert(1)" onClick="track()">link</a>
+            <a href="/page" title="javascript: the good parts">book</a>
+            <form action="JavaScript:void(0)"><button formaction="/go">go</button></form>
+            <noscript><img src="real.jpg" onerror="x()"><p>Enable JavaScript</p></noscript>
+            <script>document.write("x")</script>
+            <iframe srcdoc="<script>alert(1)</script>" src="/frame"></iframe>
+            <embed src="movie.swf"><object data="applet.jar"><p>fallback</p></object>
+            <svg><a><animate attributeName="href" values="/a; javascript:alert(1)"/><set attributeName="href" to="JavaScript:alert(1)"/>
+            <animate attributeName="opacity" from="0" to="1"/><text>svg</text></a></svg>
+        </body></html>"#);
+
+        apply(&html);
+
+        let body = |s: String| s.split_whitespace().collect::<Vec<_>>().join(" ");
+
+        assert_eq!(body(html.select_first("head").unwrap().as_node().to_string()), body(r#"<head>
+            <script type="application/ld+json">{"@type": "Article"}</script>
+            <link href="site.css" rel="stylesheet">
+            <link href="noscript.css" rel="stylesheet">
+            <meta content="text/html; charset=utf-8" http-equiv="content-type">
+        </head>"#.to_owned()));
+
+        assert_eq!(body(html.select_first("body").unwrap().as_node().to_string()), body(r#"<body>
+            <a>link</a>
+            <a href="/page" title="javascript: the good parts">book</a>
+            <form><button formaction="/go">go</button></form>
+            <img src="real.jpg"><p>Enable JavaScript</p>
+            <iframe src="/frame"></iframe>
+            <svg><a>
+            <animate attributeName="opacity" from="0" to="1"></animate><text>svg</text></a></svg>
+        </body>"#.to_owned()));
+    }
+}
@@ -0,0 +1,305 @@
+use anyhow::{Result, anyhow, bail};
+use kuchiki::NodeRef;
+
+use std::str::FromStr;
+
+// what `vw` in `sizes` is taken relative to, and the slot when there is no `sizes`
+const VIEWPORT: f64 = 1280.0;
+
+/// Which `srcset` candidates end up in the page
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Policy {
+    /// Inline every candidate, the browser still gets to choose
+    All,
+    Largest,
+    Smallest,
+    /// The smallest candidate for this device pixel ratio
+    Dpr(f64),
+    /// The smallest candidate at least this many pixels wide
+    Width(f64),
+}
+
+impl FromStr for Policy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let number = |v: &str| v.parse::<f64>()
+                                .ok()
+                                .filter(|v| *v > 0.0)
+                                .ok_or_else(|| anyhow!("`{}` is not a positive number", v));
+
+        match s.split_once(':') {
+            None if s == "all" => Ok(Policy::All),
+            None if s == "largest" => Ok(Policy::Largest),
+            None if s == "smallest" => Ok(Policy::Smallest),
+            Some(("dpr", v)) => Ok(Policy::Dpr(number(v)?)),
+            Some(("width", v)) => Ok(Policy::Width(number(v)?)),
+            _ => bail!("unknown policy `{}` (use all, largest, smallest, dpr:N or width:N)", s),
+        }
+    }
+}
+
+/// One `url descriptor` of a `srcset`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Candidate {
+    pub url: String,
+    pub descriptor: String,
+}
+
+impl Candidate {
+    // `(density, width)` in a slot of `slot` CSS pixels
+    fn size(&self, slot: f64) -> (f64, f64) {
+        let value = |suffix: char| self.descriptor
+                                       .split_ascii_whitespace()
+                                       .find_map(|d| d.strip_suffix(suffix)?.parse::<f64>().ok())
+                                       .filter(|v| *v > 0.0);
+
+        match (value('w'), value('x')) {
+            (Some(width), _) => (width / slot, width),
+            (None, density) => {
+                let density = density.unwrap_or(1.0);
+                (density, density * slot)
+            }
+        }
+    }
+}
+
+/// Candidates of a `srcset`; URLs may have commas (`data:` ones do) but not whitespace
+pub fn parse(srcset: &str) -> Vec<Candidate> {
+    let mut candidates = vec![];
+    let mut rest = srcset;
+
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_ascii_whitespace() || c == ',');
+
+        if rest.is_empty() {
+            return candidates;
+        }
+
+        let end = rest.find(|c: char| c.is_ascii_whitespace()).unwrap_or(rest.len());
+        let (url, tail) = rest.split_at(end);
+
+        // `a.png, b.png 2x`: a trailing comma ends a candidate without descriptors
+        let (url, descriptor) = match url.strip_suffix(',') {
+            Some(url) => {
+                rest = tail;
+                (url.trim_end_matches(','), "")
+            }
+            None => {
+                let mut depth = 0usize;
+                let stop = tail.char_indices()
+                               .find(|&(_, c)| match c {
+                                   '(' => { depth += 1; false }
+                                   ')' => { depth = depth.saturating_sub(1); false }
+                                   ',' => depth == 0,
+                                   _ => false,
+                               })
+                               .map_or(tail.len(), |(i, _)| i);
+
+                rest = &tail[stop..];
+                (url, tail[..stop].trim())
+            }
+        };
+
+        candidates.push(Candidate {
+            url: url.to_owned(),
+            descriptor: descriptor.to_owned(),
+        });
+    }
+}
+
+pub fn serialize(candidates: &[Candidate]) -> String {
+    candidates.iter()
+              .map(|c| match c.descriptor.is_empty() {
+                  true => c.url.to_owned(),
+                  false => format!("{} {}", c.url, c.descriptor),
+              })
+              .collect::<Vec<_>>()
+              .join(", ")
+}
+
+/// Width in CSS pixels of the slot an image is shown in, from the last `sizes` entry
+pub fn slot(sizes: Option<&str>) -> f64 {
+    let last = sizes.and_then(|s| s.rsplit(',').next()).unwrap_or_default().trim();
+
+    let px = last.strip_suffix("px").and_then(|v| v.parse::<f64>().ok());
+    let vw = last.strip_suffix("vw").and_then(|v| v.parse::<f64>().ok()).map(|v| v / 100.0 * VIEWPORT);
+
+    px.or(vw).filter(|v| *v > 0.0).unwrap_or(VIEWPORT)
+}
+
+/// The candidate `policy` wants out of candidates and the slot they are for
+pub fn pick(candidates: &[(Candidate, f64)], policy: Policy) -> Option<&Candidate> {
+    let sized = candidates.iter()
+                          .map(|(c, slot)| (c, c.size(*slot)))
+                          .collect::<Vec<_>>();
+
+    let by = |key: fn(&(f64, f64)) -> f64| move |a: &&(&Candidate, (f64, f64)), b: &&(&Candidate, (f64, f64))| {
+        key(&a.1).total_cmp(&key(&b.1))
+    };
+
+    let density = |s: &(f64, f64)| s.0;
+    let width = |s: &(f64, f64)| s.1;
+
+    // the smallest one that is enough, or the best there is
+    let enough = |key: fn(&(f64, f64)) -> f64, target: f64| {
+        sized.iter()
+             .filter(|c| key(&c.1) >= target)
+             .min_by(by(key))
+             .or_else(|| sized.iter().rev().max_by(by(key)))
+    };
+
+    let chosen = match policy {
+        Policy::All => return None,
+        // `rev()` as the first of equals wins, like it does in browsers
+        Policy::Largest => sized.iter().rev().max_by(by(density)),
+        Policy::Smallest => sized.iter().min_by(by(density)),
+        Policy::Dpr(dpr) => enough(density, dpr),
+        Policy::Width(px) => enough(width, px),
+    };
+
+    chosen.map(|(c, _)| *c)
+}
+
+/// Leaves one image in `src` of each `<img srcset>` and `<picture>`, the rest isn't fetched
+pub fn collapse(html: &NodeRef, policy: Policy) {
+    if policy == Policy::All {
+        return;
+    }
+
+    for picture in html.select("picture").map_or(vec![], |v| v.collect()) {
+        let img = match picture.as_node().select_first("img") {
+            Ok(img) => img,
+            Err(_) => continue,
+        };
+
+        let sources = picture.as_node()
+                             .select("source[srcset]")
+                             .map_or(vec![], |v| v.collect());
+
+        // art direction for other screens only if there is nothing else
+        let general = sources.iter()
+                             .filter(|s| !s.attributes.borrow().contains("media"))
+                             .collect::<Vec<_>>();
+
+        let mut candidates = match general.is_empty() {
+            true => sources.iter().collect::<Vec<_>>(),
+            false => general,
+        }
+        .into_iter()
+        .flat_map(|s| {
+            let attrs = s.attributes.borrow();
+            let slot = slot(attrs.get("sizes"));
+            parse(attrs.get("srcset").unwrap_or_default()).into_iter().map(move |c| (c, slot))
+        })
+        .collect::<Vec<_>>();
+
+        candidates.extend(img_candidates(&img.attributes.borrow()));
+
+        if let Some(chosen) = pick(&candidates, policy) {
+            log!(debug, "<picture> is {}", chosen.url);
+
+            let mut attrs = img.attributes.borrow_mut();
+            attrs.insert("src", chosen.url.to_owned());
+            attrs.remove("srcset");
+            attrs.remove("sizes");
+        }
+
+        sources.iter().for_each(|s| s.as_node().detach());
+    }
+
+    for img in html.select("img[srcset]").map_or(vec![], |v| v.collect()) {
+        let candidates = img_candidates(&img.attributes.borrow());
+
+        if let Some(chosen) = pick(&candidates, policy).cloned() {
+            let mut attrs = img.attributes.borrow_mut();
+            attrs.insert("src", chosen.url);
+            attrs.remove("srcset");
+            attrs.remove("sizes");
+        }
+    }
+}
+
+// `srcset`, and `src` as the 1x one unless it is a placeholder
+fn img_candidates(attrs: &kuchiki::Attributes) -> Vec<(Candidate, f64)> {
+    let slot = slot(attrs.get("sizes"));
+
+    let mut candidates = parse(attrs.get("srcset").unwrap_or_default())
+        .into_iter()
+        .map(|c| (c, slot))
+        .collect::<Vec<_>>();
+
+    if let Some(src) = attrs.get("src").filter(|src| !src.trim().is_empty() && !src.starts_with("data:")) {
+        candidates.push((Candidate { url: src.trim().to_owned(), descriptor: String::new() }, slot));
+    }
+
+    candidates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use kuchiki::traits::*;
+
+    fn candidate(url: &str, descriptor: &str) -> Candidate {
+        Candidate {
+            url: url.to_owned(),
+            descriptor: descriptor.to_owned(),
+        }
+    }
+
+    #[test]
+    fn parse_srcset() {
+        assert_eq!(parse(" a.png 1x,b.png  2x ,\n c.png"),
+                   [candidate("a.png", "1x"), candidate("b.png", "2x"), candidate("c.png", "")]);
+        assert_eq!(parse("data:image/png;base64,iVBO= 1x, a,b.png 480w"),
+                   [candidate("data:image/png;base64,iVBO=", "1x"), candidate("a,b.png", "480w")]);
+        assert_eq!(parse("a.png,, b.png 2x"), [candidate("a.png", ""), candidate("b.png", "2x")]);
+        assert_eq!(parse("a.png,b.png 2x"), [candidate("a.png,b.png", "2x")]);
+        assert_eq!(parse(""), []);
+
+        assert_eq!(serialize(&parse("a.png 1x,b.png 2x")), "a.png 1x, b.png 2x");
+    }
+
+    #[test]
+    fn policies() {
+        let densities = parse("a.png 1x, b.png 2x, c.png 3x").into_iter().map(|c| (c, slot(None))).collect::<Vec<_>>();
+        let widths = parse("s.jpg 320w, m.jpg 800w, l.jpg 1600w").into_iter().map(|c| (c, slot(Some("(max-width: 600px) 100vw, 400px")))).collect::<Vec<_>>();
+
+        let url = |candidates: &[(Candidate, f64)], policy: &str| pick(candidates, policy.parse().unwrap()).map(|c| c.url.to_owned());
+
+        assert_eq!(url(&densities, "largest").as_deref(), Some("c.png"));
+        assert_eq!(url(&densities, "smallest").as_deref(), Some("a.png"));
+        assert_eq!(url(&densities, "dpr:1.5").as_deref(), Some("b.png"));
+        assert_eq!(url(&densities, "dpr:4").as_deref(), Some("c.png"));
+        assert_eq!(url(&densities, "all"), None);
+
+        // a 400px slot: 800w is 2x
+        assert_eq!(url(&widths, "dpr:2").as_deref(), Some("m.jpg"));
+        assert_eq!(url(&widths, "width:500").as_deref(), Some("m.jpg"));
+        assert_eq!(url(&widths, "width:5000").as_deref(), Some("l.jpg"));
+
+        assert!("dpr:0".parse::<Policy>().is_err());
+        assert!("biggest".parse::<Policy>().is_err());
+    }
+
+    #[test]
+    fn collapse_images() {
+        let html = kuchiki::parse_html().one(r#"
+            <picture>
+                <source media="(max-width: 600px)" srcset="crop.webp">
+                <source type="image/webp" srcset="a.webp 1x, a@2x.webp 2x">
+                <img src="a.jpg" srcset="a@2x.jpg 2x" alt="a">
+            </picture>
+            <img src="data:," srcset="b.jpg 1x, b@2x.jpg 2x">
+        "#);
+
+        collapse(&html, Policy::Largest);
+
+        let body = html.select_first("body").unwrap().as_node().to_string();
+        let body = body.split_whitespace().collect::<Vec<_>>().join(" ");
+
+        assert_eq!(body, r#"<body><picture> <img alt="a" src="a@2x.webp"> </picture> <img src="b@2x.jpg"> </body>"#);
+    }
+}
@@ -2,8 +2,11 @@ use super::*;
 
 use std::env;
 use std::fs::File;
+use std::io::Write;
+use std::net::TcpListener;
 use std::path::Path;
 use std::sync::Once;
+use std::thread;
 
 const TESTDATA_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/test_data");
 
@@ -173,6 +176,86 @@ body {{
     );
 }
 
+#[test]
+fn rebase_stylesheet() {
+    // references are relative to the stylesheet, those that can't be inlined stay as they were
+    test(
+        &[ handler::css::EXTERN ],
+        r#"<link href="assets/rebase/site.css" rel="stylesheet">"#,
+        &format!(r#"<style type="text/css">b {{
+    background-image: url({});
+}}
+u {{
+    background-image: url(gone.png);
+}}
+
+@import "absent.css";
+p {{
+    background-image: url({});
+}}
+i {{
+    background-image: url(missing.png);
+}}
+</style>"#, *GIF, *PNG),
+    );
+}
+
+#[test]
+fn precompressed_sibling() {
+    test(
+        &[handler::css::EXTERN],
+        r#"<link href="assets/gz/packed.css" rel="stylesheet">"#,
+        r#"<style type="text/css">p {
+    color: green;
+}
+</style>"#,
+    );
+}
+
+#[test]
+fn keep_data_uri() {
+    let html = r#"<img src="data:image/gif;base64,R0lGODdh"><i style="background: url(data:image/gif;base64,R0lGODdh)"></i>"#;
+
+    test(
+        &[ handler::image::TAG, handler::css::INLINE ],
+        html,
+        html,
+    );
+}
+
+#[test]
+fn max_size_only_for_resources() {
+    setup();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    // 20K, with a `Content-Length` on `/sized` and without on `/streamed`
+    thread::spawn(move || for stream in listener.incoming() {
+        let mut stream = stream.unwrap();
+        let mut request = [0; 1024];
+        let n = stream.read(&mut request).unwrap_or_default();
+
+        let length = match request[..n].starts_with(b"GET /sized") {
+            true => "Content-Length: 20000\r\n",
+            false => "",
+        };
+
+        let _ = write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\n{}Connection: close\r\n\r\n{}", length, "x".repeat(20_000));
+    });
+
+    let url = |path: &str| Url::parse(&format!("http://{}/{}", addr, path)).unwrap();
+
+    // the page, filter lists and injected files
+    assert_eq!(utils::load_url(&url("sized")).unwrap().data.len(), 20_000);
+
+    // what the page refers to
+    for path in ["sized", "streamed"] {
+        let err = http::get(&url(path), Some(10_000)).err().unwrap();
+        assert!(matches!(err.downcast_ref(), Some(report::LoadError::Filtered(_))), "{}", err);
+    }
+}
+
 #[allow(dead_code)]
 fn read_bytes<P: AsRef<Path>>(path: P) -> Vec<u8> {
     let mut f = File::open(Path::new(TESTDATA_PATH).join(path)).unwrap();
@@ -0,0 +1,113 @@
+use once_cell::sync::Lazy;
+use url::Url;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+static HOSTS: Lazy<Mutex<HashMap<String, Arc<Host>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Connections in flight and the earliest time the next request may start
+#[derive(Debug)]
+struct Host {
+    active: Mutex<usize>,
+    freed: Condvar,
+    next: Mutex<Instant>,
+}
+
+/// Taken for the duration of a request, gives the connection back when dropped
+#[derive(Debug)]
+pub struct Slot {
+    host: Arc<Host>,
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        *self.host.active.lock().expect("cannot reach host slots") -= 1;
+        self.host.freed.notify_one();
+    }
+}
+
+/// Blocks until `url`'s host has fewer than `connections` requests running
+/// and at least 1/`rate` of a second has passed since the last one started
+pub fn acquire(url: &Url, connections: usize, rate: f64) -> Slot {
+    let key = format!("{}:{}",
+                      url.host_str().unwrap_or_default(),
+                      url.port_or_known_default().unwrap_or_default());
+
+    let host = HOSTS.lock()
+                    .expect("cannot reach hosts")
+                    .entry(key)
+                    .or_insert_with(|| Arc::new(Host {
+                        active: Mutex::new(0),
+                        freed: Condvar::new(),
+                        next: Mutex::new(Instant::now()),
+                    }))
+                    .clone();
+
+    {
+        let mut active = host.active.lock().expect("cannot reach host slots");
+
+        while *active >= connections.max(1) {
+            active = host.freed.wait(active).expect("cannot reach host slots");
+        }
+
+        *active += 1;
+    }
+
+    if rate > 0.0 {
+        // book a start time first, sleep outside of the lock
+        let start = {
+            let mut next = host.next.lock().expect("cannot reach host schedule");
+            let start = (*next).max(Instant::now());
+            *next = start + Duration::from_secs_f64(1.0 / rate);
+            start
+        };
+
+        let wait = start.saturating_duration_since(Instant::now());
+
+        if !wait.is_zero() {
+            log!(debug, "{} is rate limited, waiting {:?}", url, wait);
+            thread::sleep(wait);
+        }
+    }
+
+    Slot { host }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn host_limits() {
+        let url = Url::parse("https://throttle.example.com/").unwrap();
+        let running = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+
+        let started = Instant::now();
+
+        let workers = (0..8)
+            .map(|_| {
+                let (url, running, peak) = (url.clone(), running.clone(), peak.clone());
+
+                thread::spawn(move || {
+                    let _slot = acquire(&url, 2, 100.0);
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(now, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(10));
+                    running.fetch_sub(1, Ordering::SeqCst);
+                })
+            })
+            .collect::<Vec<_>>();
+
+        workers.into_iter().for_each(|w| w.join().unwrap());
+
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+        // 8 requests at 100/s: the last one starts 70ms after the first
+        assert!(started.elapsed() >= Duration::from_millis(70));
+    }
+}
@@ -0,0 +1,153 @@
+use anyhow::{Context, Result, anyhow, bail};
+use once_cell::sync::OnceCell;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{CryptoProvider, ring};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime, pem::PemObject};
+use rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+
+use std::error::Error;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+// set in main() when --cacert, --cert or --insecure is given, ureq's defaults otherwise
+pub static CONFIG: OnceCell<Arc<ClientConfig>> = OnceCell::new();
+
+/// Webpki roots plus `cacert`, optional client certificate, or no verification at all
+pub fn config(cacert: Option<&Path>, cert: Option<&Path>, key: Option<&Path>, insecure: bool) -> Result<ClientConfig> {
+    let provider = Arc::new(ring::default_provider());
+
+    let builder = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+
+    let builder = if insecure {
+        log!(warn, "TLS certificates are not verified (--insecure)");
+
+        builder.dangerous()
+               .with_custom_certificate_verifier(Arc::new(NoVerifier(provider)))
+    } else {
+        let mut roots = RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        };
+
+        if let Some(path) = cacert {
+            let (added, ignored) = roots.add_parsable_certificates(certificates(path)?);
+            log!(info, "{} CA certificate(s) loaded from {}, {} ignored", added, path.display(), ignored);
+
+            if added == 0 {
+                bail!("{}: no usable CA certificates", path.display());
+            }
+        }
+
+        builder.with_root_certificates(roots)
+    };
+
+    let config = match (cert, key) {
+        (Some(cert), key) => {
+            // key may come along with the certificate in one PEM file
+            let key = key.unwrap_or(cert);
+            let key = PrivateKeyDer::from_pem_file(key)
+                .map_err(|e| anyhow!("{}: cannot read private key: {}", key.display(), e))?;
+
+            builder.with_client_auth_cert(certificates(cert)?, key)
+                   .context("client certificate does not match the key")?
+        }
+        (None, Some(_)) => bail!("--key needs --cert"),
+        (None, None) => builder.with_no_client_auth(),
+    };
+
+    Ok(config)
+}
+
+fn certificates(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|iter| iter.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| anyhow!("{}: cannot read certificates: {}", path.display(), e))?;
+
+    if certs.is_empty() {
+        bail!("{}: no PEM certificates found", path.display());
+    }
+
+    Ok(certs)
+}
+
+/// Points at the way out when a transport error is a failed certificate check
+pub fn explain(err: &ureq::Transport) -> Option<String> {
+    let mut source = err.source();
+
+    while let Some(e) = source {
+        let tls = e.downcast_ref::<rustls::Error>()
+                   .or_else(|| e.downcast_ref::<io::Error>()
+                                .and_then(|e| e.get_ref())
+                                .and_then(|e| e.downcast_ref::<rustls::Error>()));
+
+        match tls {
+            Some(e @ rustls::Error::InvalidCertificate(_)) => {
+                return Some(format!("TLS certificate verification failed: {} \
+                                     (use --cacert FILE for a private CA, or --insecure)", e));
+            }
+            Some(e @ rustls::Error::AlertReceived(_)) => {
+                return Some(format!("TLS handshake rejected by the server: {} \
+                                     (does it need a client certificate? see --cert and --key)", e));
+            }
+            _ => source = e.source(),
+        }
+    }
+
+    None
+}
+
+// --insecure: any certificate for any name, signatures are still checked
+#[derive(Debug)]
+struct NoVerifier(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for NoVerifier {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer,
+        _intermediates: &[CertificateDer],
+        _server_name: &ServerName,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bad_bundles() {
+        let path = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/test_data/test.html"));
+
+        let err = config(Some(path), None, None, false).unwrap_err();
+        assert!(err.to_string().contains("no PEM certificates found"), "{}", err);
+
+        assert!(config(None, None, Some(path), false).is_err());
+        assert!(config(None, None, None, true).is_ok());
+    }
+}
@@ -1,13 +1,24 @@
 use crate::OPT;
-
-use anyhow::{Result, anyhow, bail};
+use crate::adblock;
+use crate::archive::{self, Fallback};
+use crate::audit;
+use crate::charset;
+use crate::compress;
+use crate::filter;
+use crate::har;
+use crate::http;
+use crate::report::{self, LoadError};
+use crate::rewrite;
+
+use anyhow::{Result, bail};
 use once_cell::unsync::Lazy;
-use kuchiki::{NodeDataRef, ElementData};
+use kuchiki::{Attributes, NodeDataRef, ElementData};
 use url::Url;
 
-use std::cell::Cell;
+use std::cell::{Cell, Ref, RefCell, RefMut};
 use std::fs;
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime};
 
 const OCTET_STREAM: &str = "application/octet-stream";
 
@@ -84,15 +95,134 @@ macro_rules! regex {
     }
 }
 
-pub fn load_url(url: &Url) -> Result<(String, Vec<u8>)> {
+// handler and node the current job works on
+thread_local!(
+    static INITIATOR: RefCell<Option<Initiator>> = const { RefCell::new(None) }
+);
+
+#[derive(Debug, Clone)]
+pub struct Initiator {
+    pub handler: String,
+    pub element: String,
+    pub attributes: Vec<(String, String)>,
+    pub node: String,
+}
+
+impl Initiator {
+    pub fn new(handler: &str, node: &NodeDataRef<ElementData>) -> Initiator {
+        Initiator {
+            handler: handler.to_owned(),
+            element: node.name.local.to_string(),
+            attributes: retry!(node.attributes.try_borrow())
+                            .unwrap()
+                            .map
+                            .iter()
+                            .map(|(k, v)| (k.local.to_string(), v.value.to_owned()))
+                            .collect(),
+            node: format_node(node),
+        }
+    }
+}
+
+pub fn initiator() -> Option<Initiator> {
+    INITIATOR.with(|i| i.borrow().clone())
+}
+
+// Nested `par_iter()`s may run on other threads so the initiator
+// has to be carried over explicitly
+pub fn with_initiator<T>(initiator: Option<Initiator>, f: impl FnOnce() -> T) -> T {
+    let prev = INITIATOR.with(|i| i.replace(initiator));
+    let res = f();
+    INITIATOR.with(|i| i.replace(prev));
+    res
+}
+
+pub struct Response {
+    /// Where the response came from, after redirects
+    pub url: Url,
+    pub status: i32,
+    pub reason: String,
+    pub request_headers: Vec<(String, String)>,
+    pub headers: Vec<(String, String)>,
+    pub mime: String,
+    pub data: Vec<u8>,
+    /// Time the request itself took, zero for files and archives
+    pub time: Duration,
+    /// 3xx responses that led here, first one first
+    pub redirects: Vec<Response>,
+}
+
+impl Response {
+    fn ok(url: Url, mime: String, data: Vec<u8>) -> Response {
+        Response {
+            url,
+            status: 200,
+            reason: "OK".to_owned(),
+            request_headers: vec![],
+            headers: vec![],
+            mime,
+            data,
+            time: Duration::ZERO,
+            redirects: vec![],
+        }
+    }
+
+    fn redirect(url: Url, status: i32, location: String) -> Response {
+        Response {
+            url,
+            status,
+            reason: String::new(),
+            request_headers: vec![],
+            headers: vec![("Location".to_owned(), location)],
+            mime: String::new(),
+            data: vec![],
+            time: Duration::ZERO,
+            redirects: vec![],
+        }
+    }
+}
+
+/// Whole response of a URL, whatever its size; resources go through `load()`
+pub fn load_url(url: &Url) -> Result<Response> {
+    load_url_within(url, None)
+}
+
+fn load_url_within(url: &Url, max_size: Option<usize>) -> Result<Response> {
+    let started = SystemTime::now();
+    let timer = Instant::now();
+
+    let resp = fetch(url, max_size);
+    har::record(url, started, timer.elapsed(), &resp);
+
+    let resp = resp?;
+
+    if !(200..300).contains(&resp.status) {
+        return Err(LoadError::Status(resp.status).into());
+    }
+
+    Ok(resp)
+}
+
+fn fetch(url: &Url, max_size: Option<usize>) -> Result<Response> {
     match url.scheme() {
         "file" => {
             log!(info, "reading file://{}", url.path());
 
             let path = url.to_file_path()
-                          .map_err(|_| anyhow!("cannot get path"))?;
+                          .map_err(|_| LoadError::Url("cannot get path".to_owned()))?;
 
-            let data = fs::read(&path)?;
+            // pre-compressed `file.css.gz` or `file.css.br` stand in for a missing `file.css`
+            let packed = Some(&path).filter(|p| !p.exists())
+                                    .and_then(|p| compress::sibling(p));
+
+            let data = match packed {
+                Some((packed, coding)) => {
+                    log!(debug, "reading {} instead", packed.display());
+                    let data = fs::read(&packed).map_err(|e| LoadError::Fetch(e.to_string()))?;
+                    compress::decode(data, coding)?
+                }
+                None => fs::read(&path).map_err(|e| LoadError::Fetch(e.to_string()))?,
+            };
 
             let mime = guess_mime(&data)
                           .or_else(|| path.extension()
@@ -100,41 +230,124 @@ pub fn load_url(url: &Url) -> Result<(String, Vec<u8>)> {
                           .and_then(guess_mime_by_ext))
                           .unwrap_or(OCTET_STREAM.to_owned());
 
-            Ok((mime, data))
+            Ok(Response::ok(url.to_owned(), mime, data))
         }
         "http" | "https" => {
+            if let Some(archive) = archive::ARCHIVE.get() {
+                match archive.get(url, OPT.max_redirects) {
+                    Some(res) => return res.map(|archive::Archived { url, mime, data, redirects }| {
+                        let mime = mime.or_else(|| guess_mime(&data))
+                                       .unwrap_or(OCTET_STREAM.to_owned());
+
+                        let mut resp = Response::ok(url, mime, data);
+                        resp.redirects = redirects.into_iter()
+                                                  .map(|(url, status, location)| Response::redirect(url, status, location))
+                                                  .collect();
+                        resp
+                    }),
+                    None if OPT.archive_fallback == Fallback::Network => {
+                        log!(info, "{} is not archived", url.as_str());
+                    }
+                    None => return Err(LoadError::NotArchived.into()),
+                }
+            }
+
             log!(info, "requesting {}", url.as_str());
 
-            let resp = minreq::get(url.as_ref()).send()?;
+            let mut resp = http::get(url, max_size)?;
 
-            if resp.status_code != 200 {
-                bail!("Response status code: {}", resp.status_code);
+            if resp.mime.is_empty() {
+                resp.mime = guess_mime(&resp.data).unwrap_or(OCTET_STREAM.to_owned());
             }
 
-            let mime = resp.headers.get("content-type")
-                                   .map(ToOwned::to_owned)
-                                   .or_else(|| guess_mime(resp.as_bytes()))
-                                   .unwrap_or(OCTET_STREAM.to_owned());
-
-            let data = resp.into_bytes();
-
-            Ok((mime, data))
+            Ok(resp)
         }
-        _ => Err(anyhow!("not supported URL scheme"))
+        scheme => Err(LoadError::Scheme(scheme.to_owned()).into())
     }
 }
 
 pub fn load_file(href: &str) -> Result<(String, Vec<u8>)> {
+    load_file_or(href, None)
+}
+
+/// `load_file()` that tries `fallback` if `href` cannot be loaded
+pub fn load_file_or(href: &str, fallback: Option<&str>) -> Result<(String, Vec<u8>)> {
+    reported(href, load_or(href, fallback).map(|resp| (resp.mime, resp.data)))
+}
+
+pub fn load_string(href: &str) -> Result<String> {
+    load_string_and_url(href).map(|(_, content)| content)
+}
+
+/// Text, decoded to UTF-8, and the URL it ended up at to resolve references relative to it
+pub fn load_string_and_url(href: &str) -> Result<(Url, String)> {
+    load_string_and_url_or(href, None)
+}
+
+/// `load_string_and_url()` that tries `fallback` if `href` cannot be loaded
+pub fn load_string_and_url_or(href: &str, fallback: Option<&str>) -> Result<(Url, String)> {
+    reported(href, load_or(href, fallback).map(|resp| {
+        let content = charset::decode_text(&resp.data, &resp.mime);
+        (resp.url, content)
+    }))
+}
+
+fn resolve(href: &str) -> Result<Url, LoadError> {
     Url::parse(href)
         .or_else(|_| OPT.get_base().join(href))
-        .map(|u| load_url(&u))?
+        .map_err(|e| LoadError::Url(e.to_string()))
 }
 
-pub fn load_string(href: &str) -> Result<String> {
-    load_file(href).and_then(|(_, content)| {
-        String::from_utf8(content)
-            .map_err(anyhow::Error::msg)
-    })
+// The error of `href` is the one that counts; a dry run doesn't get to the fallback
+fn load_or(href: &str, fallback: Option<&str>) -> Result<Response> {
+    match (load(href), fallback) {
+        (Err(e), Some(fallback)) if !OPT.list && resolve(fallback).ok() != resolve(href).ok() => {
+            log!(debug, "cannot load {}, trying {}", href, fallback);
+            load(fallback).map_err(|_| e)
+        }
+        (res, _) => res,
+    }
+}
+
+fn load(href: &str) -> Result<Response> {
+    let url = resolve(href)?;
+    let filtered = filter::check_url(&url).and_then(|_| adblock::check(&url));
+
+    if OPT.list {
+        audit::record(href, &url, filtered.err().map(|e| match e {
+            LoadError::Filtered(why) => why,
+            LoadError::Blocked(rule) => format!("blocked by {}", rule),
+            e => e.to_string(),
+        }));
+        bail!("dry run, not fetching {}", url);
+    }
+
+    filtered?;
+
+    let max_size = filter::max_size(&url);
+
+    let resp = match rewrite::apply(&OPT.maps, &url) {
+        Some(mapped) => {
+            log!(info, "{} is mapped to {}", url, mapped);
+            load_url_within(&mapped, max_size)?
+        }
+        None => load_url_within(&url, max_size)?,
+    };
+
+    filter::check_response(&resp, max_size)?;
+
+    Ok(resp)
+}
+
+// Counts every resource a handler asked for as inlined or failed
+fn reported<T>(href: &str, res: Result<T>) -> Result<T> {
+    match res {
+        Ok(_) => report::inlined(),
+        Err(_) if OPT.list => {}
+        Err(ref e) => report::failed(&resolve(href).map_or_else(|_| href.to_owned(), |u| u.to_string()), e),
+    }
+
+    res
 }
 
 fn guess_mime(data: &[u8]) -> Option<String> {
@@ -180,24 +393,53 @@ fn guess_mime_by_ext(ext: &str) -> Option<String> {
 }
 
 pub fn make_data_uri(url: &mut String) {
-    if let Ok((mime, data)) = load_file(url) {
+    make_data_uri_or(url, None)
+}
+
+/// `make_data_uri()` that tries `fallback` if `url` cannot be loaded
+pub fn make_data_uri_or(url: &mut String, fallback: Option<&str>) {
+    if url.starts_with("data:") {
+        return;
+    }
+
+    if let Ok((mime, data)) = load_file_or(url, fallback) {
         *url = format!("data:{};base64,{}", mime, base64::encode(data));
     }
 }
 
 #[allow(dead_code)]
 pub fn make_data_uri_with_mime(mime: &str) -> impl Fn(&mut String) + '_ {
-    move |url| if let Ok((_, data)) = load_file(url) {
+    move |url| if url.starts_with("data:") {
+        // already inlined
+    } else if let Ok((_, data)) = load_file(url) {
         *url = format!("data:{};base64,{}", mime, base64::encode(data));
     }
 }
 
+/// Attributes of the node a handler works on; if they stay borrowed
+/// the node is reported as failed and `None` is returned
+pub fn attributes(node: &NodeDataRef<ElementData>) -> Option<Ref<'_, Attributes>> {
+    retry!(node.attributes.try_borrow())
+        .map_err(|_| node_failed(node, "attributes are being written"))
+        .ok()
+}
+
+/// Same as `attributes()`, to change them
+pub fn attributes_mut(node: &NodeDataRef<ElementData>) -> Option<RefMut<'_, Attributes>> {
+    retry!(node.attributes.try_borrow_mut())
+        .map_err(|_| node_failed(node, "attributes are in use"))
+        .ok()
+}
+
+fn node_failed(node: &NodeDataRef<ElementData>, why: &str) {
+    report::failed(&format!("<{}>", node.name.local), &LoadError::Node(why.to_owned()).into());
+}
+
 pub fn format_node(node: &NodeDataRef<ElementData>) -> String {
     format!("<{} {} />",
 
             node.name
-                .local
-                .to_string(),
+                .local,
 
             retry!(node.attributes.try_borrow())
                 .unwrap()